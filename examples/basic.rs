@@ -1,6 +1,17 @@
 extern crate agentsql;
+use agentdb::AgentDB;
 use agentsql::*;
 
-fn main() {
-    println!("Run example!");
+#[tokio::main]
+async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let db = SqlBackend::sqlite(":memory:").await?;
+
+    let tx = db.transaction().await?;
+    tx.put("greeting", b"hello".to_vec().into()).await?;
+    tx.commit().await?;
+
+    let value = db.get("greeting").await?.expect("committed");
+    println!("greeting = {}", String::from_utf8_lossy(value.as_bytes()));
+
+    Ok(())
 }
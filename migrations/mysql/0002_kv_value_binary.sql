@@ -0,0 +1,4 @@
+-- Store kv_store values as raw bytes instead of TEXT
+-- Converting TEXT to LONGBLOB keeps the stored utf8mb4 bytes unchanged
+
+ALTER TABLE kv_store MODIFY value LONGBLOB NOT NULL;
@@ -0,0 +1,6 @@
+-- Optional expiry time of kv_store entries, in Unix milliseconds
+-- NULL means the entry never expires
+
+ALTER TABLE kv_store
+    ADD COLUMN expires_at BIGINT NULL,
+    ADD INDEX idx_kv_store_expires_at (expires_at);
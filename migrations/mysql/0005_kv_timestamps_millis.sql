@@ -0,0 +1,15 @@
+-- Store kv_store created_at and updated_at in Unix milliseconds, like
+-- expires_at. Existing values are in seconds, except that writing NOW()
+-- into the BIGINT updated_at left YYYYMMDDhhmmss numbers behind
+
+UPDATE kv_store SET
+    updated_at = IF(updated_at >= 10000000000000, UNIX_TIMESTAMP(updated_at), updated_at);
+
+UPDATE kv_store SET
+    created_at = COALESCE(created_at, updated_at, UNIX_TIMESTAMP()) * 1000,
+    updated_at = COALESCE(updated_at, created_at DIV 1000, UNIX_TIMESTAMP()) * 1000;
+
+ALTER TABLE kv_store
+    MODIFY created_at BIGINT NOT NULL DEFAULT (CAST(UNIX_TIMESTAMP(NOW(3)) * 1000 AS SIGNED)),
+    MODIFY updated_at BIGINT NOT NULL DEFAULT (CAST(UNIX_TIMESTAMP(NOW(3)) * 1000 AS SIGNED)),
+    ADD INDEX idx_kv_store_updated_at (updated_at);
@@ -0,0 +1,6 @@
+-- Compare and sort kv_store keys byte-wise through the column itself, so
+-- key range scans can use the primary key index
+-- utf8mb4_0900_bin orders by code point, i.e. byte-wise, and unlike
+-- utf8mb4_bin does not ignore trailing spaces. Keys become case-sensitive
+
+ALTER TABLE kv_store MODIFY `key` VARCHAR(255) CHARACTER SET utf8mb4 COLLATE utf8mb4_0900_bin NOT NULL;
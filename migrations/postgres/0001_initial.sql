@@ -29,12 +29,12 @@ CREATE INDEX IF NOT EXISTS idx_fs_dentry_parent ON fs_dentry(parent_ino, name);
 CREATE TABLE IF NOT EXISTS fs_data (
     id BIGSERIAL PRIMARY KEY,
     ino BIGINT NOT NULL,
-    offset BIGINT NOT NULL,
+    "offset" BIGINT NOT NULL,
     size BIGINT NOT NULL,
     data BYTEA NOT NULL
 );
 
-CREATE INDEX IF NOT EXISTS idx_fs_data_ino_offset ON fs_data(ino, offset);
+CREATE INDEX IF NOT EXISTS idx_fs_data_ino_offset ON fs_data(ino, "offset");
 
 -- Symbolic link table: Stores symlink targets
 CREATE TABLE IF NOT EXISTS fs_symlink (
@@ -0,0 +1,4 @@
+-- Store kv_store values as raw bytes instead of TEXT
+-- Existing rows keep their UTF-8 encoding
+
+ALTER TABLE kv_store ALTER COLUMN value TYPE BYTEA USING convert_to(value, 'UTF8');
@@ -0,0 +1,4 @@
+-- Per-key version, incremented by every write, for compare-and-swap
+-- Existing entries start at version 1
+
+ALTER TABLE kv_store ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
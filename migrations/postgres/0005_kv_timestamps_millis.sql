@@ -0,0 +1,14 @@
+-- Store kv_store created_at and updated_at in Unix milliseconds, like
+-- expires_at. Existing values are in seconds
+
+UPDATE kv_store SET
+    created_at = COALESCE(created_at, updated_at, EXTRACT(EPOCH FROM NOW())::BIGINT) * 1000,
+    updated_at = COALESCE(updated_at, created_at, EXTRACT(EPOCH FROM NOW())::BIGINT) * 1000;
+
+ALTER TABLE kv_store
+    ALTER COLUMN created_at SET DEFAULT (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT,
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT (EXTRACT(EPOCH FROM NOW()) * 1000)::BIGINT,
+    ALTER COLUMN updated_at SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_kv_store_updated_at ON kv_store(updated_at);
@@ -0,0 +1,5 @@
+-- Compare and sort kv_store keys byte-wise through the column itself, so
+-- key range scans can use the primary key index
+-- The "C" collation orders UTF-8 text by code point, i.e. byte-wise
+
+ALTER TABLE kv_store ALTER COLUMN key TYPE TEXT COLLATE "C";
@@ -2,8 +2,8 @@
 -- Based on Agent Filesystem Specification (SPEC.md)
 -- Optimized for embedded, single-file storage
 
-PRAGMA foreign_keys = ON;
-PRAGMA journal_mode = WAL;
+-- Connection pragmas (foreign_keys, journal_mode) are applied by the backend
+-- on connect, since PRAGMA journal_mode cannot run inside a transaction
 
 -- Inode table: Stores file and directory metadata
 CREATE TABLE IF NOT EXISTS fs_inode (
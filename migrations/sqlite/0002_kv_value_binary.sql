@@ -0,0 +1,18 @@
+-- Store kv_store values as raw bytes instead of TEXT
+-- SQLite cannot change a column type in place, so rebuild the table
+
+CREATE TABLE kv_store_binary (
+    key TEXT PRIMARY KEY,
+    value BLOB NOT NULL,
+    created_at INTEGER DEFAULT (unixepoch()),
+    updated_at INTEGER DEFAULT (unixepoch())
+);
+
+INSERT INTO kv_store_binary (key, value, created_at, updated_at)
+SELECT key, CAST(value AS BLOB), created_at, updated_at FROM kv_store;
+
+DROP TABLE kv_store;
+
+ALTER TABLE kv_store_binary RENAME TO kv_store;
+
+CREATE INDEX IF NOT EXISTS idx_kv_store_created_at ON kv_store(created_at);
@@ -0,0 +1,6 @@
+-- Optional expiry time of kv_store entries, in Unix milliseconds
+-- NULL means the entry never expires
+
+ALTER TABLE kv_store ADD COLUMN expires_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_kv_store_expires_at ON kv_store(expires_at);
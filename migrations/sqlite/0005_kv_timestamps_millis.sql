@@ -0,0 +1,48 @@
+-- Store kv_store created_at and updated_at in Unix milliseconds, like
+-- expires_at. Older writes left seconds or datetime('now') text behind.
+-- SQLite cannot change a column default in place, so rebuild the table
+
+CREATE TABLE kv_store_millis (
+    key TEXT PRIMARY KEY,
+    value BLOB NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (CAST(unixepoch('subsec') * 1000 AS INTEGER)),
+    updated_at INTEGER NOT NULL DEFAULT (CAST(unixepoch('subsec') * 1000 AS INTEGER)),
+    expires_at INTEGER,
+    version INTEGER NOT NULL DEFAULT 1
+);
+
+INSERT INTO kv_store_millis (key, value, created_at, updated_at, expires_at, version)
+SELECT
+    key,
+    value,
+    COALESCE(
+        CASE typeof(created_at)
+            WHEN 'integer' THEN created_at * 1000
+            WHEN 'text' THEN unixepoch(created_at) * 1000
+        END,
+        CAST(unixepoch('subsec') * 1000 AS INTEGER)
+    ),
+    COALESCE(
+        CASE typeof(updated_at)
+            WHEN 'integer' THEN updated_at * 1000
+            WHEN 'text' THEN unixepoch(updated_at) * 1000
+        END,
+        CASE typeof(created_at)
+            WHEN 'integer' THEN created_at * 1000
+            WHEN 'text' THEN unixepoch(created_at) * 1000
+        END,
+        CAST(unixepoch('subsec') * 1000 AS INTEGER)
+    ),
+    expires_at,
+    version
+FROM kv_store;
+
+DROP TABLE kv_store;
+
+ALTER TABLE kv_store_millis RENAME TO kv_store;
+
+CREATE INDEX IF NOT EXISTS idx_kv_store_created_at ON kv_store(created_at);
+
+CREATE INDEX IF NOT EXISTS idx_kv_store_updated_at ON kv_store(updated_at);
+
+CREATE INDEX IF NOT EXISTS idx_kv_store_expires_at ON kv_store(expires_at);
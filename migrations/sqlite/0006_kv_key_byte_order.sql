@@ -0,0 +1,5 @@
+-- Compare and sort kv_store keys byte-wise through the column itself, so
+-- key range scans can use the primary key index
+-- SQLite's default BINARY collation already orders keys this way
+
+SELECT 1;
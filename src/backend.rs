@@ -3,21 +3,33 @@
 //! Provides a single backend type that works with SQLite, PostgreSQL, and MySQL
 //! using runtime dispatch based on the connection URL.
 
-use crate::{error::Result, SqlError};
+use crate::{
+    error::Result,
+    health::{CountedPool, HealthReport, PoolStats},
+    kv::{
+        CasOutcome, EntryMetadata, EntryStream, ListPage, ScanOptions, ScanPage, VersionedValue,
+    },
+    migrate::{self, MigrationMode, MigrationSet, MigrationStatus, Migrator},
+    ops,
+    ttl::{self, Sweeper},
+    value::{SqlQueryResult, SqlRow, SqlValue},
+    SqlError, SqlTransaction,
+};
 use agentdb::{
-    AgentDB, BackendFamily, Capabilities, DefaultCapabilities, QueryResult, Row, ScanResult,
+    AgentDB, BackendFamily, Capabilities, DefaultCapabilities, QueryResult, ScanResult,
     Transaction, Value,
 };
 use async_trait::async_trait;
-use sqlx::{
-    any::{install_default_drivers, AnyRow},
-    AnyPool, Column, Row as SqlxRow,
-};
+use serde::de::DeserializeOwned;
+use sqlx::{any::install_default_drivers, pool::PoolOptions, Any};
+use std::time::Duration;
 
 /// Configuration for SQL backend
 #[derive(Debug, Clone)]
 pub enum SqlBackendConfig {
-    /// SQLite: file path or ":memory:"
+    /// SQLite: file path, `":memory:"` for a private in-memory database, or
+    /// `":memory:<name>"` for an in-memory database shared by every backend
+    /// in the process that uses the same name
     #[cfg(feature = "sqlite")]
     Sqlite(String),
 
@@ -30,17 +42,541 @@ pub enum SqlBackendConfig {
     Mysql(String),
 }
 
+/// Environment variable read by [`SqlBackendConfig::from_env`]
+pub const DATABASE_URL_ENV: &str = "AGENTSQL_DATABASE_URL";
+
+impl SqlBackendConfig {
+    /// Pick the backend from a connection URL
+    ///
+    /// Accepts `sqlite::memory:`, `sqlite:<path>` (also `sqlite://<path>`),
+    /// `postgres://`, `postgresql://` and `mysql://` URLs. Fails with
+    /// [`SqlError::Connection`] for unknown schemes, and for schemes whose
+    /// cargo feature is not compiled in.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use agentsql::SqlBackendConfig;
+    ///
+    /// let config = SqlBackendConfig::from_url("sqlite::memory:").unwrap();
+    /// assert!(matches!(config, SqlBackendConfig::Sqlite(path) if path == ":memory:"));
+    /// ```
+    pub fn from_url(url: &str) -> Result<Self> {
+        let Some((scheme, rest)) = url.split_once(':') else {
+            return Err(SqlError::Connection(format!(
+                "Invalid database URL '{}': missing scheme",
+                url
+            )));
+        };
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "sqlite" => Self::sqlite_from_url(url, rest),
+            "postgres" | "postgresql" => {
+                #[cfg(feature = "postgres")]
+                return Ok(SqlBackendConfig::Postgres(url.to_string()));
+                #[cfg(not(feature = "postgres"))]
+                return Err(missing_feature(scheme, "postgres"));
+            }
+            "mysql" => {
+                #[cfg(feature = "mysql")]
+                return Ok(SqlBackendConfig::Mysql(url.to_string()));
+                #[cfg(not(feature = "mysql"))]
+                return Err(missing_feature(scheme, "mysql"));
+            }
+            _ => Err(SqlError::Connection(format!(
+                "Unsupported database URL scheme '{}' (expected sqlite, postgres, postgresql or mysql)",
+                scheme
+            ))),
+        }
+    }
+
+    /// Read the connection URL from the `AGENTSQL_DATABASE_URL` environment variable
+    pub fn from_env() -> Result<Self> {
+        let url = std::env::var(DATABASE_URL_ENV).map_err(|e| {
+            SqlError::Connection(format!("Failed to read {}: {}", DATABASE_URL_ENV, e))
+        })?;
+        Self::from_url(&url)
+    }
+
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn sqlite_from_url(url: &str, rest: &str) -> Result<Self> {
+        #[cfg(not(feature = "sqlite"))]
+        return Err(missing_feature("sqlite", "sqlite"));
+
+        #[cfg(feature = "sqlite")]
+        {
+            if rest == ":memory:" || rest == "//:memory:" {
+                return Ok(SqlBackendConfig::Sqlite(":memory:".to_string()));
+            }
+
+            // Connection flags are chosen by the backend itself
+            if rest.contains('?') {
+                return Err(SqlError::Connection(format!(
+                    "Query parameters are not supported in SQLite URL '{}'",
+                    url
+                )));
+            }
+
+            let path = rest.strip_prefix("//").unwrap_or(rest);
+            if path.is_empty() {
+                return Err(SqlError::Connection(format!(
+                    "SQLite URL '{}' has no database path",
+                    url
+                )));
+            }
+            Ok(SqlBackendConfig::Sqlite(path.to_string()))
+        }
+    }
+}
+
+#[cfg(not(all(feature = "sqlite", feature = "postgres", feature = "mysql")))]
+fn missing_feature(scheme: &str, feature: &str) -> SqlError {
+    SqlError::Connection(format!(
+        "Database URL scheme '{}' requires the `{}` cargo feature of agentsql",
+        scheme, feature
+    ))
+}
+
+/// Optional settings for [`SqlBackend::with_options`]
+#[derive(Debug, Clone, Default)]
+pub struct SqlBackendOptions {
+    migration_mode: MigrationMode,
+    migration_sets: Vec<MigrationSet>,
+    max_connections: Option<u32>,
+    min_connections: Option<u32>,
+    acquire_timeout: Option<Duration>,
+    idle_timeout: Option<Option<Duration>>,
+    max_lifetime: Option<Option<Duration>>,
+    test_before_acquire: Option<bool>,
+    statement_cache_size: Option<usize>,
+    #[cfg(feature = "sqlite")]
+    sqlite_pragmas: SqlitePragmas,
+    read_replica: Option<String>,
+    read_only: bool,
+    table_prefix: String,
+    #[cfg(feature = "postgres")]
+    postgres_schema: Option<String>,
+    ttl_sweep_interval: Option<Duration>,
+    ttl_sweep_batch_size: Option<u32>,
+}
+
+impl SqlBackendOptions {
+    /// Default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What to do with pending migrations on connect (default: [`MigrationMode::Auto`])
+    pub fn migration_mode(mut self, mode: MigrationMode) -> Self {
+        self.migration_mode = mode;
+        self
+    }
+
+    /// Register an application migration set
+    ///
+    /// Registered sets run after the built-in schema, in registration order,
+    /// under the same migration lock. Each set is versioned independently
+    /// in the ledger under its own name.
+    pub fn migration_set(mut self, set: MigrationSet) -> Self {
+        self.migration_sets.push(set);
+        self
+    }
+
+    /// Maximum number of pooled connections (sqlx default: 10)
+    pub fn max_connections(mut self, max: u32) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Number of connections the pool keeps open even when idle (default: 0)
+    pub fn min_connections(mut self, min: u32) -> Self {
+        self.min_connections = Some(min);
+        self
+    }
+
+    /// How long to wait for a free connection before failing (default: 30s)
+    pub fn acquire_timeout(mut self, timeout: Duration) -> Self {
+        self.acquire_timeout = Some(timeout);
+        self
+    }
+
+    /// Close connections idle for longer than this; `None` keeps them (default: 10min)
+    ///
+    /// In-memory SQLite databases always keep their connections, since the
+    /// database is dropped together with its last connection.
+    pub fn idle_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.idle_timeout = Some(timeout.into());
+        self
+    }
+
+    /// Close connections older than this; `None` keeps them (default: 30min)
+    pub fn max_lifetime(mut self, lifetime: impl Into<Option<Duration>>) -> Self {
+        self.max_lifetime = Some(lifetime.into());
+        self
+    }
+
+    /// Ping connections before handing them out (default: true)
+    pub fn test_before_acquire(mut self, test: bool) -> Self {
+        self.test_before_acquire = Some(test);
+        self
+    }
+
+    /// Number of prepared statements cached per connection
+    ///
+    /// Applied to PostgreSQL and MySQL. sqlx's `Any` driver cannot configure
+    /// the SQLite statement cache, which keeps its default of 100.
+    pub fn statement_cache_size(mut self, size: usize) -> Self {
+        self.statement_cache_size = Some(size);
+        self
+    }
+
+    /// Send reads to a replica of the primary database
+    ///
+    /// `get`, `exists`, `scan` and SELECT queries, including `WITH` queries
+    /// that only read, run on the replica, while writes, transactions and
+    /// migrations stay on the primary. The URL is
+    /// parsed like [`SqlBackendConfig::from_url`] and must use the same
+    /// backend as the primary; pool settings apply to both pools. Use
+    /// [`SqlBackend::primary`] for reads that must see the latest writes.
+    pub fn read_replica(mut self, url: impl Into<String>) -> Self {
+        self.read_replica = Some(url.into());
+        self
+    }
+
+    /// Open the database strictly read-only
+    ///
+    /// SQLite files are opened with `mode=ro`, and PostgreSQL and MySQL
+    /// sessions are set read-only. Migrations are not run on connect
+    /// ([`MigrationMode::Verify`] still checks the schema), and `put`,
+    /// `delete`, non-SELECT queries and scripts fail with
+    /// [`SqlError::ReadOnly`] before reaching the database.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Prefix prepended to every built-in table and index name
+    ///
+    /// Backends with different prefixes keep separate `kv_store`, filesystem,
+    /// `tool_calls` and `schema_migrations` tables, so several agents can
+    /// share one database. Use [`SqlBackend::table_name`] to name the tables
+    /// in raw queries. Registered migration sets run verbatim. The prefix may
+    /// hold up to 32 ASCII letters, digits and underscores.
+    pub fn table_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.table_prefix = prefix.into();
+        self
+    }
+
+    /// Keep all tables in this PostgreSQL schema
+    ///
+    /// The schema is created if missing and set as the `search_path` of every
+    /// connection, so raw queries resolve unqualified names in it too.
+    /// Connecting to any other backend with a schema set fails.
+    #[cfg(feature = "postgres")]
+    pub fn postgres_schema(mut self, schema: impl Into<String>) -> Self {
+        self.postgres_schema = Some(schema.into());
+        self
+    }
+
+    /// Delete expired key-value entries in the background every `interval`
+    ///
+    /// Expired entries are hidden from reads either way; the sweeper only
+    /// reclaims their space. Without it, call [`SqlBackend::purge_expired`].
+    /// Read-only backends never start the sweeper. A zero interval fails
+    /// to connect with [`SqlError::Connection`].
+    pub fn ttl_sweeper(mut self, interval: Duration) -> Self {
+        self.ttl_sweep_interval = Some(interval);
+        self
+    }
+
+    /// Rows deleted per statement when purging expired entries (default: 1000)
+    pub fn ttl_sweep_batch_size(mut self, batch_size: u32) -> Self {
+        self.ttl_sweep_batch_size = Some(batch_size.max(1));
+        self
+    }
+
+    /// Pragmas applied to every new SQLite connection
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite_pragmas(mut self, pragmas: SqlitePragmas) -> Self {
+        self.sqlite_pragmas = pragmas;
+        self
+    }
+
+    fn pool_options(&self) -> PoolOptions<Any> {
+        let mut pool = PoolOptions::new();
+        if let Some(max) = self.max_connections {
+            pool = pool.max_connections(max);
+        }
+        if let Some(min) = self.min_connections {
+            pool = pool.min_connections(min);
+        }
+        if let Some(timeout) = self.acquire_timeout {
+            pool = pool.acquire_timeout(timeout);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            pool = pool.idle_timeout(timeout);
+        }
+        if let Some(lifetime) = self.max_lifetime {
+            pool = pool.max_lifetime(lifetime);
+        }
+        if let Some(test) = self.test_before_acquire {
+            pool = pool.test_before_acquire(test);
+        }
+        pool
+    }
+}
+
+/// SQLite `journal_mode` values
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+#[cfg(feature = "sqlite")]
+impl JournalMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+}
+
+/// SQLite `synchronous` values
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+#[cfg(feature = "sqlite")]
+impl Synchronous {
+    fn as_str(self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+/// Connection settings applied to every new SQLite pool connection
+///
+/// Defaults to WAL journaling, foreign keys on and a 5 second busy timeout;
+/// every other pragma keeps SQLite's own default unless set.
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlitePragmas {
+    journal_mode: Option<JournalMode>,
+    synchronous: Option<Synchronous>,
+    busy_timeout: Option<Duration>,
+    foreign_keys: Option<bool>,
+    cache_size: Option<i64>,
+    mmap_size: Option<u64>,
+}
+
+#[cfg(feature = "sqlite")]
+impl Default for SqlitePragmas {
+    fn default() -> Self {
+        Self {
+            journal_mode: Some(JournalMode::Wal),
+            synchronous: None,
+            busy_timeout: Some(Duration::from_secs(5)),
+            foreign_keys: Some(true),
+            cache_size: None,
+            mmap_size: None,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqlitePragmas {
+    /// Default pragmas
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `PRAGMA journal_mode`; `None` keeps SQLite's default
+    pub fn journal_mode(mut self, mode: impl Into<Option<JournalMode>>) -> Self {
+        self.journal_mode = mode.into();
+        self
+    }
+
+    /// `PRAGMA synchronous`; `None` keeps SQLite's default
+    pub fn synchronous(mut self, synchronous: impl Into<Option<Synchronous>>) -> Self {
+        self.synchronous = synchronous.into();
+        self
+    }
+
+    /// `PRAGMA busy_timeout`, how long a writer waits for a lock before
+    /// failing with "database is locked"
+    pub fn busy_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.busy_timeout = timeout.into();
+        self
+    }
+
+    /// `PRAGMA foreign_keys`
+    pub fn foreign_keys(mut self, enabled: impl Into<Option<bool>>) -> Self {
+        self.foreign_keys = enabled.into();
+        self
+    }
+
+    /// `PRAGMA cache_size`; positive values count pages, negative values KiB
+    pub fn cache_size(mut self, size: impl Into<Option<i64>>) -> Self {
+        self.cache_size = size.into();
+        self
+    }
+
+    /// `PRAGMA mmap_size` in bytes; 0 disables memory-mapped I/O
+    pub fn mmap_size(mut self, size: impl Into<Option<u64>>) -> Self {
+        self.mmap_size = size.into();
+        self
+    }
+
+    /// PRAGMA statements, busy timeout first so the others can wait for locks
+    fn statements(&self) -> Vec<String> {
+        let mut statements = Vec::new();
+        if let Some(timeout) = self.busy_timeout {
+            statements.push(format!("PRAGMA busy_timeout = {}", timeout.as_millis()));
+        }
+        if let Some(mode) = self.journal_mode {
+            statements.push(format!("PRAGMA journal_mode = {}", mode.as_str()));
+        }
+        if let Some(synchronous) = self.synchronous {
+            statements.push(format!("PRAGMA synchronous = {}", synchronous.as_str()));
+        }
+        if let Some(enabled) = self.foreign_keys {
+            let enabled = if enabled { "ON" } else { "OFF" };
+            statements.push(format!("PRAGMA foreign_keys = {}", enabled));
+        }
+        if let Some(size) = self.cache_size {
+            statements.push(format!("PRAGMA cache_size = {}", size));
+        }
+        if let Some(size) = self.mmap_size {
+            statements.push(format!("PRAGMA mmap_size = {}", size));
+        }
+        statements
+    }
+
+    /// Run the pragmas on every connection the pool opens
+    ///
+    /// A read-only connection cannot switch the journal mode, so that pragma
+    /// is left out there.
+    fn install(&self, pool: PoolOptions<Any>, read_only: bool) -> PoolOptions<Any> {
+        let pragmas = if read_only {
+            Self { journal_mode: None, ..self.clone() }
+        } else {
+            self.clone()
+        };
+        let statements: std::sync::Arc<[String]> = pragmas.statements().into();
+        pool.after_connect(move |conn, _meta| {
+            let statements = statements.clone();
+            Box::pin(async move {
+                for statement in statements.iter() {
+                    sqlx::query(statement).execute(&mut *conn).await?;
+                }
+                Ok(())
+            })
+        })
+    }
+}
+
+/// Name of the in-memory database a SQLite path refers to, if any
+///
+/// `:memory:` gets a fresh unique name; `:memory:<name>` uses `name`.
+#[cfg(feature = "sqlite")]
+fn memory_database_name(path: &str) -> Result<Option<String>> {
+    let Some(name) = path.strip_prefix(":memory:") else {
+        return Ok(None);
+    };
+
+    if name.is_empty() {
+        return Ok(Some(uuid::Uuid::new_v4().simple().to_string()));
+    }
+
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(SqlError::Connection(format!(
+            "Invalid in-memory database name '{}': use ASCII letters, digits, '-', '_' and '.'",
+            name
+        )));
+    }
+    Ok(Some(name.to_string()))
+}
+
+/// Reject identifiers that are not plain ASCII words of at most `max_len` characters
+fn validate_identifier(what: &str, name: &str, max_len: usize) -> Result<()> {
+    let valid = name.len() <= max_len
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(SqlError::Connection(format!(
+            "Invalid {} '{}': use at most {} ASCII letters, digits and '_'",
+            what, name, max_len
+        )));
+    }
+    Ok(())
+}
+
+/// Run `statements` on every connection the pool opens
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+fn session_statements(pool: PoolOptions<Any>, statements: Vec<String>) -> PoolOptions<Any> {
+    let statements: std::sync::Arc<[String]> = statements.into();
+    pool.after_connect(move |conn, _meta| {
+        let statements = statements.clone();
+        Box::pin(async move {
+            for statement in statements.iter() {
+                sqlx::query(statement).execute(&mut *conn).await?;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Append a `statement-cache-capacity` parameter to a server URL
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+fn with_statement_cache(url: String, size: Option<usize>) -> String {
+    match size {
+        Some(size) => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{}{}statement-cache-capacity={}", url, separator, size)
+        }
+        None => url,
+    }
+}
+
 /// Unified SQL backend using SQLx
 ///
 /// Supports SQLite, PostgreSQL, and MySQL with a single interface.
 pub struct SqlBackend {
-    pool: AnyPool,
-    backend_type: BackendType,
+    pool: CountedPool,
+    // Serves reads when configured
+    replica: Option<CountedPool>,
+    ctx: SqlContext,
     capabilities: DefaultCapabilities,
+    // Built-in schema first, then registered sets
+    migration_sets: Vec<MigrationSet>,
+    read_only: bool,
+    sweep_batch_size: u32,
+    sweeper: Option<Sweeper>,
 }
 
+/// Database a [`SqlBackend`] is connected to
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum BackendType {
+pub enum BackendType {
     #[cfg(feature = "sqlite")]
     Sqlite,
     #[cfg(feature = "postgres")]
@@ -76,19 +612,139 @@ impl SqlBackend {
     /// }
     /// ```
     pub async fn new(config: SqlBackendConfig) -> Result<Self> {
+        Self::with_options(config, SqlBackendOptions::default()).await
+    }
+
+    /// Create a new SQL backend with additional options
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use agentsql::{Migration, MigrationSet, SqlBackend, SqlBackendConfig, SqlBackendOptions};
+    ///
+    /// let notes = MigrationSet::new("notes").migration(
+    ///     Migration::new(1, "create_notes")
+    ///         .sqlite("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)")
+    ///         .postgres("CREATE TABLE notes (id BIGSERIAL PRIMARY KEY, body TEXT)"),
+    /// );
+    /// let db = SqlBackend::with_options(
+    ///     SqlBackendConfig::Sqlite("agent.db".into()),
+    ///     SqlBackendOptions::new().migration_set(notes),
+    /// )
+    /// .await?;
+    /// ```
+    pub async fn with_options(
+        config: SqlBackendConfig,
+        mut options: SqlBackendOptions,
+    ) -> Result<Self> {
+        let mut migration_sets = vec![migrate::builtin()];
+        for set in std::mem::take(&mut options.migration_sets) {
+            set.validate()?;
+            if migration_sets.iter().any(|s| s.name() == set.name()) {
+                return Err(SqlError::Migration(format!(
+                    "Migration set '{}' is registered more than once",
+                    set.name()
+                )));
+            }
+            migration_sets.push(set);
+        }
+
+        validate_identifier("table prefix", &options.table_prefix, 32)?;
+        if options.ttl_sweep_interval == Some(Duration::ZERO) {
+            return Err(SqlError::Connection(
+                "TTL sweeper interval must be greater than zero".to_string(),
+            ));
+        }
+
         // Install default SQLx drivers
         install_default_drivers();
 
-        let (url, backend_type, capabilities, is_memory) = match config {
+        let (url, backend_type, capabilities, is_memory) = Self::resolve(config, &options)?;
+        #[cfg(feature = "postgres")]
+        if let Some(schema) = &options.postgres_schema {
+            if backend_type != BackendType::Postgres {
+                return Err(SqlError::Connection(format!(
+                    "A PostgreSQL schema was set but the backend is {:?}",
+                    backend_type
+                )));
+            }
+            validate_identifier("PostgreSQL schema", schema, 63)?;
+        }
+        let pool = Self::connect_pool(&url, backend_type, is_memory, &options).await?;
+
+        let replica = match &options.read_replica {
+            Some(replica_url) => {
+                let (url, replica_type, _, is_memory) =
+                    Self::resolve(SqlBackendConfig::from_url(replica_url)?, &options)?;
+                if replica_type != backend_type {
+                    return Err(SqlError::Connection(format!(
+                        "Read replica is {:?} but the primary is {:?}",
+                        replica_type, backend_type
+                    )));
+                }
+                Some(Self::connect_pool(&url, backend_type, is_memory, &options).await?)
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "postgres")]
+        if let Some(schema) = options.postgres_schema.as_ref().filter(|_| !options.read_only) {
+            let mut conn = pool.acquire().await?;
+            sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS \"{}\"", schema))
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        let mut backend = Self {
+            pool,
+            replica,
+            ctx: SqlContext::new(backend_type, &options.table_prefix),
+            capabilities,
+            migration_sets,
+            read_only: options.read_only,
+            sweep_batch_size: options.ttl_sweep_batch_size.unwrap_or(ttl::DEFAULT_BATCH_SIZE),
+            sweeper: None,
+        };
+
+        match options.migration_mode {
+            MigrationMode::Auto if options.read_only => {}
+            MigrationMode::Auto => backend.migrate().await?,
+            MigrationMode::Verify => backend.verify_migrations().await?,
+            MigrationMode::Skip => {}
+        }
+
+        if let Some(interval) = options.ttl_sweep_interval.filter(|_| !options.read_only) {
+            backend.sweeper = Some(Sweeper::spawn(
+                backend.pool.clone(),
+                backend.ctx.clone(),
+                interval,
+                backend.sweep_batch_size,
+            ));
+        }
+
+        Ok(backend)
+    }
+
+    /// Connection URL, backend type, capabilities and in-memory flag for `config`
+    #[cfg_attr(not(any(feature = "postgres", feature = "mysql")), allow(unused_variables))]
+    fn resolve(
+        config: SqlBackendConfig,
+        options: &SqlBackendOptions,
+    ) -> Result<(String, BackendType, DefaultCapabilities, bool)> {
+        Ok(match config {
             #[cfg(feature = "sqlite")]
             SqlBackendConfig::Sqlite(path) => {
-                let is_memory = path == ":memory:";
-                let url = if is_memory {
-                    // For :memory: databases, use shared cache mode to ensure all connections
-                    // see the same database, and set pool size to 1
-                    "sqlite::memory:?cache=shared".to_string()
-                } else {
-                    format!("sqlite:{}?mode=rwc", path)
+                let memory_name = memory_database_name(&path)?;
+                let is_memory = memory_name.is_some();
+                let url = match memory_name {
+                    // Shared cache under a per-database name, so all connections of this
+                    // pool see the same database but other backends do not. SQLite only
+                    // shares named in-memory databases given as `file:` URIs.
+                    Some(name) => {
+                        format!("sqlite:file:agentsql-memory-{}?mode=memory&cache=shared", name)
+                    }
+                    None if options.read_only => format!("sqlite:{}?mode=ro", path),
+                    None => format!("sqlite:{}?mode=rwc", path),
                 };
                 (
                     url,
@@ -99,7 +755,7 @@ impl SqlBackend {
                         graph_queries: false,
                         sql_queries: true,
                         indexes: true,
-                        ttl: false,
+                        ttl: true,
                         max_key_size: Some(1024 * 1024),       // 1MB
                         max_value_size: Some(1024 * 1024 * 1024), // 1GB
                     },
@@ -108,7 +764,7 @@ impl SqlBackend {
             }
             #[cfg(feature = "postgres")]
             SqlBackendConfig::Postgres(url) => (
-                url,
+                with_statement_cache(url, options.statement_cache_size),
                 BackendType::Postgres,
                 DefaultCapabilities {
                     transactions: true,
@@ -116,7 +772,7 @@ impl SqlBackend {
                     graph_queries: false,
                     sql_queries: true,
                     indexes: true,
-                    ttl: false,
+                    ttl: true,
                     max_key_size: None,    // unlimited
                     max_value_size: None,  // unlimited
                 },
@@ -124,7 +780,7 @@ impl SqlBackend {
             ),
             #[cfg(feature = "mysql")]
             SqlBackendConfig::Mysql(url) => (
-                url,
+                with_statement_cache(url, options.statement_cache_size),
                 BackendType::Mysql,
                 DefaultCapabilities {
                     transactions: true,
@@ -132,38 +788,77 @@ impl SqlBackend {
                     graph_queries: false,
                     sql_queries: true,
                     indexes: true,
-                    ttl: false,
+                    ttl: true,
                     max_key_size: Some(255),  // VARCHAR(255) for keys
                     max_value_size: None,     // LONGBLOB
                 },
                 false,
             ),
-        };
+        })
+    }
 
-        // For :memory: databases, use a single connection pool
-        let pool = if is_memory {
-            use sqlx::pool::PoolOptions;
-            PoolOptions::new()
-                .max_connections(1)
-                .connect(&url)
-                .await
-                .map_err(|e| SqlError::Connection(e.to_string()))?
-        } else {
-            AnyPool::connect(&url)
-                .await
-                .map_err(|e| SqlError::Connection(e.to_string()))?
-        };
+    /// Open a pool for `url` with the pool settings from `options`
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    async fn connect_pool(
+        url: &str,
+        backend_type: BackendType,
+        is_memory: bool,
+        options: &SqlBackendOptions,
+    ) -> Result<CountedPool> {
+        let mut pool_options = options.pool_options();
 
-        let backend = Self {
-            pool,
-            backend_type,
-            capabilities,
-        };
+        match backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => {
+                pool_options = options.sqlite_pragmas.install(pool_options, options.read_only);
+            }
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => {
+                let mut statements = Vec::new();
+                if let Some(schema) = &options.postgres_schema {
+                    statements.push(format!("SET search_path TO \"{}\"", schema));
+                }
+                if options.read_only {
+                    statements
+                        .push("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY".to_string());
+                }
+                pool_options = session_statements(pool_options, statements);
+            }
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => {
+                let mut statements = Vec::new();
+                if options.read_only {
+                    statements.push("SET SESSION TRANSACTION READ ONLY".to_string());
+                }
+                pool_options = session_statements(pool_options, statements);
+            }
+        }
 
-        // Run migrations
-        backend.migrate().await?;
+        // An in-memory database lives only as long as one of its connections,
+        // so keep at least one open for the lifetime of the pool
+        if is_memory {
+            let min_connections = pool_options.get_min_connections().max(1);
+            pool_options = pool_options
+                .min_connections(min_connections)
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
 
-        Ok(backend)
+        CountedPool::connect(pool_options, url)
+            .await
+            .map_err(|e| SqlError::Connection(e.to_string()))
+    }
+
+    /// Connect to the database a URL points at
+    ///
+    /// See [`SqlBackendConfig::from_url`] for the accepted URLs.
+    pub async fn connect(url: &str) -> Result<Self> {
+        Self::new(SqlBackendConfig::from_url(url)?).await
+    }
+
+    /// Connect to the database named by `AGENTSQL_DATABASE_URL`
+    pub async fn from_env() -> Result<Self> {
+        Self::new(SqlBackendConfig::from_env()?).await
     }
 
     /// Convenience constructor for SQLite
@@ -184,83 +879,492 @@ impl SqlBackend {
         Self::new(SqlBackendConfig::Mysql(url.into())).await
     }
 
-    /// Run database migrations
-    async fn migrate(&self) -> Result<()> {
-        let sql = match self.backend_type {
-            #[cfg(feature = "sqlite")]
-            BackendType::Sqlite => include_str!("../migrations/sqlite.sql"),
-            #[cfg(feature = "postgres")]
-            BackendType::Postgres => include_str!("../migrations/postgres.sql"),
-            #[cfg(feature = "mysql")]
-            BackendType::Mysql => include_str!("../migrations/mysql.sql"),
-        };
+    /// Apply all pending migrations of the built-in schema and registered sets
+    ///
+    /// This is what [`MigrationMode::Auto`] runs on connect; call it from a
+    /// deploy job when the application connects with
+    /// [`MigrationMode::Verify`] or [`MigrationMode::Skip`].
+    pub async fn migrate(&self) -> Result<()> {
+        self.run_migrations(Migrator::new(self.ctx.clone()), &self.migration_sets).await
+    }
+
+    /// Apply pending migrations of the built-in schema up to and including `version`
+    ///
+    /// Fails with [`SqlError::Migration`] if `version` is unknown or older
+    /// than the applied version; down migrations are not supported.
+    /// Registered sets are left untouched.
+    pub async fn migrate_to(&self, version: i64) -> Result<()> {
+        let builtin = std::slice::from_ref(self.migration_set(migrate::BUILTIN_SET)?);
+        self.run_migrations(Migrator::new(self.ctx.clone()).up_to(version), builtin).await
+    }
+
+    async fn run_migrations(&self, migrator: Migrator, sets: &[MigrationSet]) -> Result<()> {
+        ops::ensure_writable(self.read_only, "migrations")?;
 
         // Get a connection from the pool
-        // Note: We execute DDL statements directly without a transaction
-        // because SQLite auto-commits DDL and wrapping in transaction can cause issues
         let mut conn = self.pool.acquire().await
             .map_err(|e| SqlError::Migration(format!("Failed to acquire connection: {}", e)))?;
 
-        // Split SQL into individual statements and execute them one by one
-        // SQLx doesn't support executing multiple statements at once with raw_sql
-        for (idx, statement) in sql.split(';').enumerate() {
-            // Remove comment lines and trim
-            let statement: String = statement
-                .lines()
-                .filter(|line| {
-                    let trimmed = line.trim();
-                    !trimmed.is_empty() && !trimmed.starts_with("--")
-                })
-                .collect::<Vec<_>>()
-                .join("\n")
-                .trim()
-                .to_string();
-
-            // Skip empty statements
-            if statement.is_empty() {
-                continue;
-            }
+        migrator.run(&mut conn, sets).await
+    }
 
-            sqlx::query(&statement)
-                .execute(&mut *conn)
-                .await
-                .map_err(|e| SqlError::Migration(format!("Failed to execute migration statement #{}: {} - Error: {}", idx, statement, e)))?;
+    /// Fail unless every migration set is fully applied and unmodified
+    async fn verify_migrations(&self) -> Result<()> {
+        let mut conn = self.pool.acquire().await
+            .map_err(|e| SqlError::Migration(format!("Failed to acquire connection: {}", e)))?;
+        Migrator::new(self.ctx.clone()).check(&mut conn, &self.migration_sets).await
+    }
+
+    /// Report the applied and pending versions of the built-in schema
+    ///
+    /// Fails with [`SqlError::Migration`] if an applied migration was edited
+    /// after it ran.
+    pub async fn migration_status(&self) -> Result<MigrationStatus> {
+        self.migration_status_of(migrate::BUILTIN_SET).await
+    }
+
+    /// Report the applied and pending versions of a registered migration set
+    ///
+    /// Fails with [`SqlError::Migration`] if no set named `set` was
+    /// registered, or if one of its applied migrations was edited.
+    pub async fn migration_status_of(&self, set: &str) -> Result<MigrationStatus> {
+        let set = self.migration_set(set)?;
+        let mut conn = self.pool.acquire().await?;
+        Migrator::new(self.ctx.clone()).status(&mut conn, set).await
+    }
+
+    fn migration_set(&self, name: &str) -> Result<&MigrationSet> {
+        self.migration_sets
+            .iter()
+            .find(|s| s.name() == name)
+            .ok_or_else(|| SqlError::Migration(format!("Unknown migration set '{}'", name)))
+    }
+
+    /// Store a key-value pair that expires after `ttl`
+    ///
+    /// Once expired, the key is invisible to `get`, `exists` and `scan`.
+    /// Expiry uses this process's clock, so agents sharing a database should
+    /// keep their clocks in sync. A plain `put` clears the expiry.
+    pub async fn put_with_ttl(&self, key: &str, value: Value, ttl: Duration) -> Result<()> {
+        ops::ensure_writable(self.read_only, "put_with_ttl")?;
+        let mut conn = self.pool.acquire().await?;
+        Ok(ops::put(&mut *conn, &self.ctx, key, &value, Some(ops::expiry(ttl))).await?)
+    }
+
+    /// Make an existing key expire after `ttl`
+    ///
+    /// Fails with `NotFound` if the key is missing or already expired.
+    pub async fn expire(&self, key: &str, ttl: Duration) -> Result<()> {
+        ops::ensure_writable(self.read_only, "expire")?;
+        let mut conn = self.pool.acquire().await?;
+        Ok(ops::expire(&mut *conn, &self.ctx, key, ops::expiry(ttl)).await?)
+    }
+
+    /// Delete all expired entries now, returning how many were removed
+    pub async fn purge_expired(&self) -> Result<u64> {
+        ops::ensure_writable(self.read_only, "purge_expired")?;
+        ttl::purge(&self.pool, &self.ctx, self.sweep_batch_size).await
+    }
+
+    /// Fetch a value together with its size, version and timestamps
+    ///
+    /// Returns `None` if the key is missing or expired.
+    pub async fn get_with_metadata(&self, key: &str) -> Result<Option<(Value, EntryMetadata)>> {
+        ops::get_with_metadata(&mut *self.read_pool().acquire().await?, &self.ctx, key).await
+    }
+
+    /// Fetch a value together with its version
+    ///
+    /// Pass the version to [`put_if_version`](Self::put_if_version) or
+    /// [`delete_if_version`](Self::delete_if_version) to write only if no
+    /// one else has written the key since. Reads the primary, so the
+    /// version is never stale because of replica lag.
+    pub async fn get_versioned(&self, key: &str) -> Result<Option<VersionedValue>> {
+        ops::get_versioned(&mut *self.pool.acquire().await?, &self.ctx, key).await
+    }
+
+    /// Replace the value of `key` only if its version is still `expected`
+    ///
+    /// Returns [`CasOutcome::Conflict`] with the current version when the
+    /// key was written, deleted or expired in the meantime.
+    pub async fn put_if_version(
+        &self,
+        key: &str,
+        value: Value,
+        expected: i64,
+    ) -> Result<CasOutcome> {
+        ops::ensure_writable(self.read_only, "put_if_version")?;
+        let mut conn = self.pool.acquire().await?;
+        ops::put_if_version(&mut conn, &self.ctx, key, &value, expected).await
+    }
+
+    /// Create `key` only if it does not exist or has expired
+    ///
+    /// Returns [`CasOutcome::Conflict`] with the current version when the
+    /// key is already present.
+    pub async fn put_if_absent(&self, key: &str, value: Value) -> Result<CasOutcome> {
+        ops::ensure_writable(self.read_only, "put_if_absent")?;
+        let mut tx = self.pool.begin().await?;
+        let outcome = ops::put_if_absent(&mut tx, &self.ctx, key, &value).await?;
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    /// Delete `key` only if its version is still `expected`
+    pub async fn delete_if_version(&self, key: &str, expected: i64) -> Result<CasOutcome> {
+        ops::ensure_writable(self.read_only, "delete_if_version")?;
+        let mut conn = self.pool.acquire().await?;
+        ops::delete_if_version(&mut conn, &self.ctx, key, expected).await
+    }
+
+    /// Atomically add `delta` to the integer stored under `key`
+    ///
+    /// Counters are stored as decimal text, so `get` returns e.g. `b"42"`.
+    /// A missing or expired key starts from 0; a live key keeps its expiry.
+    /// Fails with [`SqlError::NotAnInteger`] if the current value is not an
+    /// integer, leaving it unchanged.
+    pub async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        ops::ensure_writable(self.read_only, "increment")?;
+        let mut tx = self.pool.begin().await?;
+        let value = ops::increment(&mut tx, &self.ctx, key, delta).await?;
+        tx.commit().await?;
+        Ok(value)
+    }
+
+    /// Atomically subtract `delta` from the integer stored under `key`
+    ///
+    /// See [`increment`](Self::increment).
+    pub async fn decrement(&self, key: &str, delta: i64) -> Result<i64> {
+        self.increment(key, ops::negate(key, delta)?).await
+    }
+
+    /// Read one page of keys, with optional bounds, order and limit
+    ///
+    /// Pass the returned [`ScanPage::next`] cursor back through
+    /// [`ScanOptions::after`] to fetch the following page:
+    ///
+    /// ```rust,no_run
+    /// # use agentsql::{ScanOptions, SqlBackend};
+    /// # async fn example(db: &SqlBackend) -> agentsql::Result<()> {
+    /// let options = ScanOptions::new().prefix("agent/42/").limit(500);
+    /// let mut page = db.scan_page(&options).await?;
+    /// while let Some(cursor) = page.next.take() {
+    ///     page = db.scan_page(&options.clone().after(cursor)).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn scan_page(&self, options: &ScanOptions) -> Result<ScanPage> {
+        ops::scan_page(&mut *self.read_pool().acquire().await?, &self.ctx, options).await
+    }
+
+    /// List the keys directly under `prefix` and the common prefixes one level down
+    ///
+    /// Like S3's `ListObjects`: a key whose remainder after `prefix` contains
+    /// `delimiter` is rolled up into a single common prefix ending with the
+    /// delimiter. With keys `a/1`, `a/b/1` and `a/b/2`,
+    /// `list("a/", "/")` returns the key `a/1` and the common prefix `a/b/`.
+    /// An empty delimiter lists every key under `prefix`.
+    pub async fn list(&self, prefix: &str, delimiter: &str) -> Result<ListPage> {
+        self.list_page(&ScanOptions::new().prefix(prefix), delimiter).await
+    }
+
+    /// Read one page of a delimited listing, see [`list`](Self::list)
+    ///
+    /// Keys and common prefixes share the page limit and come back in one
+    /// order, so either may end a page. Pass the returned [`ListPage::next`]
+    /// cursor back through [`ScanOptions::after`] to fetch the following page:
+    ///
+    /// ```rust,no_run
+    /// # use agentsql::{ScanOptions, SqlBackend};
+    /// # async fn example(db: &SqlBackend) -> agentsql::Result<()> {
+    /// let options = ScanOptions::new().prefix("agent/42/memory/").limit(100);
+    /// let mut page = db.list_page(&options, "/").await?;
+    /// loop {
+    ///     for dir in &page.common_prefixes {
+    ///         println!("{}", dir);
+    ///     }
+    ///     let Some(cursor) = page.next.take() else { break };
+    ///     page = db.list_page(&options.clone().after(cursor), "/").await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn list_page(&self, options: &ScanOptions, delimiter: &str) -> Result<ListPage> {
+        let mut conn = self.read_pool().acquire().await?;
+        ops::list_page(&mut conn, &self.ctx, options, delimiter).await
+    }
+
+    /// Stream every entry under `prefix` with its value and metadata, in key order
+    ///
+    /// Rows are fetched incrementally over a single query, so reading a large
+    /// namespace uses constant memory:
+    ///
+    /// ```rust,no_run
+    /// # use agentsql::SqlBackend;
+    /// # use futures_util::StreamExt;
+    /// # async fn example(db: &SqlBackend) -> agentsql::Result<()> {
+    /// let mut entries = db.scan_entries("agent/42/memory/").await?;
+    /// while let Some(entry) = entries.next().await {
+    ///     let (key, value, metadata) = entry?;
+    ///     println!("{} ({} bytes, v{})", key, value.as_bytes().len(), metadata.version);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn scan_entries(&self, prefix: &str) -> Result<EntryStream> {
+        let conn = self.read_pool().acquire().await?;
+        let (query, params) = ops::entries_query(&self.ctx, &ScanOptions::new().prefix(prefix));
+        Ok(EntryStream::spawn(conn, query, params))
+    }
+
+    /// Store many key-value pairs atomically
+    ///
+    /// Entries are written with multi-row upserts, chunked to stay within
+    /// the dialect's bind parameter limit, inside one transaction. A key
+    /// listed more than once keeps its last value; any expiry is cleared.
+    pub async fn put_many(&self, entries: &[(String, Value)]) -> Result<()> {
+        ops::ensure_writable(self.read_only, "put_many")?;
+        let mut tx = self.pool.begin().await?;
+        ops::put_many(&mut tx, &self.ctx, entries).await?;
+        Ok(tx.commit().await?)
+    }
+
+    /// Fetch many keys at once, returning values in the order of `keys`
+    ///
+    /// All lookups read one consistent snapshot; missing and expired keys
+    /// come back as `None`.
+    pub async fn get_many<K: AsRef<str>>(&self, keys: &[K]) -> Result<Vec<Option<Value>>> {
+        let mut tx = self.read_pool().begin().await?;
+        let values = ops::get_many(&mut tx, &self.ctx, keys).await?;
+        tx.commit().await?;
+        Ok(values)
+    }
+
+    /// Delete many keys atomically, returning how many existed
+    ///
+    /// Unlike `delete`, missing keys are not an error.
+    pub async fn delete_many<K: AsRef<str>>(&self, keys: &[K]) -> Result<u64> {
+        ops::ensure_writable(self.read_only, "delete_many")?;
+        let mut tx = self.pool.begin().await?;
+        let removed = ops::delete_many(&mut tx, &self.ctx, keys).await?;
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    /// Execute a multi-statement SQL script
+    ///
+    /// Statements are split on `;` with the same dialect-aware splitter the
+    /// migration runner uses, so string literals, comments, trigger bodies
+    /// and PostgreSQL `$$` function bodies may contain semicolons. All
+    /// statements run in order on a single connection, outside of a
+    /// transaction; use [`SqlTransaction::execute_script`] for atomicity.
+    pub async fn execute_script(&self, sql: &str) -> Result<()> {
+        ops::ensure_writable(self.read_only, "execute_script")?;
+        let mut conn = self.pool.acquire().await?;
+        ops::execute_script(&mut conn, self.ctx.backend_type, sql).await
+    }
+
+    /// Execute a query, keeping the SQL type of every returned value
+    ///
+    /// `?` placeholders are bound positionally to `params` on every backend.
+    /// On PostgreSQL the jsonb `?|` and `?&` operators are left alone and
+    /// `??` is the jsonb `?` operator.
+    pub async fn query_typed(
+        &self,
+        query_str: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<SqlQueryResult> {
+        ops::ensure_query_allowed(self.read_only, self.ctx.backend_type, query_str)?;
+        let mut conn = self.query_pool(query_str).acquire().await?;
+        Ok(ops::query(&mut *conn, self.ctx.backend_type, query_str, &params).await?)
+    }
+
+    /// Execute a SELECT and deserialize each row into `T` by column name
+    ///
+    /// ```ignore
+    /// #[derive(serde::Deserialize)]
+    /// struct Call { name: String, duration_ms: Option<i64> }
+    ///
+    /// let calls: Vec<Call> = db
+    ///     .query_as(
+    ///         "SELECT name, duration_ms FROM tool_calls WHERE name = ?",
+    ///         vec!["search".into()],
+    ///     )
+    ///     .await?;
+    /// ```
+    pub async fn query_as<T: DeserializeOwned>(
+        &self,
+        query_str: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<T>> {
+        self.query_typed(query_str, params)
+            .await?
+            .rows
+            .into_iter()
+            .map(SqlRow::decode)
+            .collect()
+    }
+
+    /// Database this backend is connected to
+    pub fn backend_type(&self) -> BackendType {
+        self.ctx.backend_type
+    }
+
+    /// Name of a built-in table with the configured table prefix applied
+    ///
+    /// ```rust,no_run
+    /// # use agentsql::SqlBackend;
+    /// # async fn example(db: &SqlBackend) -> agentsql::Result<()> {
+    /// let sql = format!("SELECT name FROM {} ORDER BY started_at", db.table_name("tool_calls"));
+    /// let calls = db.query_typed(&sql, vec![]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn table_name(&self, name: &str) -> String {
+        self.ctx.table(name)
+    }
+
+    /// Run a trivial query on the primary and return its round-trip time
+    ///
+    /// Fails with [`SqlError::Connection`] if no answer arrives within
+    /// `timeout`, including the time spent waiting for a pool connection.
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration> {
+        self.pool.ping(timeout).await
+    }
+
+    /// Connection statistics of the primary pool
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Connection statistics of the read replica pool, if configured
+    pub fn replica_pool_stats(&self) -> Option<PoolStats> {
+        self.replica.as_ref().map(CountedPool::stats)
+    }
+
+    /// Ping the primary and any read replica, and report schema version and pool usage
+    ///
+    /// Fails if the whole check, including reading the schema version, takes
+    /// longer than `timeout`, e.g. because the pool is exhausted.
+    pub async fn health(&self, timeout: Duration) -> Result<HealthReport> {
+        let report = async {
+            let latency = self.pool.ping(timeout).await?;
+            let replica = match &self.replica {
+                Some(replica) => Some((replica.ping(timeout).await?, replica.stats())),
+                None => None,
+            };
+
+            Ok(HealthReport {
+                backend_type: self.ctx.backend_type,
+                latency,
+                schema_version: self.migration_status().await?.current_version,
+                pool: self.pool.stats(),
+                replica,
+            })
+        };
+
+        match tokio::time::timeout(timeout, report).await {
+            Ok(result) => result,
+            Err(_) => Err(SqlError::Connection(format!(
+                "Health check timed out after {:?}",
+                timeout
+            ))),
         }
+    }
 
-        Ok(())
+    /// Read from the primary even when a read replica is configured
+    ///
+    /// Use this when a read must observe a write made just before it.
+    pub fn primary(&self) -> PrimaryReads<'_> {
+        PrimaryReads { backend: self }
+    }
+
+    /// Pool serving `get`, `exists`, `scan` and SELECT queries
+    fn read_pool(&self) -> &CountedPool {
+        self.replica.as_ref().unwrap_or(&self.pool)
+    }
+
+    /// Pool a raw query should run on
+    fn query_pool(&self, query_str: &str) -> &CountedPool {
+        if ops::is_select(self.ctx.backend_type, query_str) {
+            self.read_pool()
+        } else {
+            &self.pool
+        }
     }
 
-    /// Convert SQLx row to AgentDB row
-    fn convert_row(&self, row: AnyRow) -> Result<Row> {
-        let mut agent_row = Row::new();
+    /// Begin a transaction
+    ///
+    /// Unlike [`AgentDB::begin`], this returns the concrete
+    /// [`SqlTransaction`] so key-value and query operations can be issued
+    /// inside it.
+    pub async fn transaction(&self) -> Result<SqlTransaction> {
+        let tx = self.pool.begin().await?;
+        Ok(SqlTransaction::new(tx, self.ctx.clone(), self.read_only))
+    }
+}
 
-        for (i, column) in row.columns().iter().enumerate() {
-            let col_name = column.name().to_string();
+impl BackendType {
+    /// Quoted name of the `kv_store` key column
+    pub(crate) fn key_column(self) -> &'static str {
+        match self {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => "key",
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => "key",
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => "`key`",
+        }
+    }
 
-            // Try to get the value as raw and convert based on what works
-            // Start with Option types to handle NULL
-            let value =
-                // Try Option<i64> to handle NULL integers
-                row.try_get::<Option<i64>, _>(i).ok().and_then(|opt| opt.map(|v| v.to_string().into_bytes()))
-                // Try Option<i32>
-                .or_else(|| row.try_get::<Option<i32>, _>(i).ok().and_then(|opt| opt.map(|v| v.to_string().into_bytes())))
-                // Try Option<String>
-                .or_else(|| row.try_get::<Option<String>, _>(i).ok().and_then(|opt| opt.map(|v| v.into_bytes())))
-                // Try Option<Vec<u8>>
-                .or_else(|| row.try_get::<Option<Vec<u8>>, _>(i).ok().and_then(|opt| opt))
-                // Try Option<f64>
-                .or_else(|| row.try_get::<Option<f64>, _>(i).ok().and_then(|opt| opt.map(|v| v.to_string().into_bytes())))
-                // Try Option<f32>
-                .or_else(|| row.try_get::<Option<f32>, _>(i).ok().and_then(|opt| opt.map(|v| v.to_string().into_bytes())))
-                // Try Option<bool>
-                .or_else(|| row.try_get::<Option<bool>, _>(i).ok().and_then(|opt| opt.map(|v| if v { b"1".to_vec() } else { b"0".to_vec() })))
-                // If all Option types return None, it's a NULL value
-                .unwrap_or_else(|| vec![]);
+    /// Most bind parameters a single statement may use
+    pub(crate) fn max_params(self) -> usize {
+        match self {
+            // SQLITE_MAX_VARIABLE_NUMBER of SQLite builds before 3.32
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => 999,
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => 65535,
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => 65535,
+        }
+    }
 
-            agent_row = agent_row.with_column(col_name, Value::new(value));
+    /// Positional bind placeholder for the `n`th (1-based) parameter
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    pub(crate) fn placeholder(self, n: usize) -> String {
+        match self {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => "?".to_string(),
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => format!("${}", n),
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => "?".to_string(),
         }
+    }
+}
+
+/// Backend type and table prefix used to build SQL for one backend
+#[derive(Debug, Clone)]
+pub(crate) struct SqlContext {
+    pub backend_type: BackendType,
+    prefix: std::sync::Arc<str>,
+}
+
+impl SqlContext {
+    pub fn new(backend_type: BackendType, prefix: &str) -> Self {
+        Self { backend_type, prefix: prefix.into() }
+    }
+
+    /// Table prefix, empty when none is configured
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
 
-        Ok(agent_row)
+    /// Name of a built-in table or index with the table prefix applied
+    pub fn table(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
     }
 }
 
@@ -275,176 +1379,155 @@ impl AgentDB for SqlBackend {
     }
 
     async fn put(&self, key: &str, value: Value) -> agentdb::Result<()> {
-        let query = match self.backend_type {
-            #[cfg(feature = "sqlite")]
-            BackendType::Sqlite => {
-                "INSERT OR REPLACE INTO kv_store (key, value, updated_at) VALUES (?1, ?2, datetime('now'))"
-            }
-            #[cfg(feature = "postgres")]
-            BackendType::Postgres => {
-                "INSERT INTO kv_store (key, value, updated_at) VALUES ($1, $2, NOW()) ON CONFLICT (key) DO UPDATE SET value = $2, updated_at = NOW()"
-            }
-            #[cfg(feature = "mysql")]
-            BackendType::Mysql => {
-                "INSERT INTO kv_store (`key`, value, updated_at) VALUES (?, ?, NOW()) ON DUPLICATE KEY UPDATE value = ?, updated_at = NOW()"
-            }
-        };
-
-        #[cfg(feature = "mysql")]
-        if matches!(self.backend_type, BackendType::Mysql) {
-            sqlx::query(query)
-                .bind(key)
-                .bind(value.as_bytes())
-                .bind(value.as_bytes())
-                .execute(&self.pool)
-                .await
-                .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
-        } else {
-            sqlx::query(query)
-                .bind(key)
-                .bind(value.as_bytes())
-                .execute(&self.pool)
-                .await
-                .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
-        }
-
-        #[cfg(not(feature = "mysql"))]
-        sqlx::query(query)
-            .bind(key)
-            .bind(value.as_bytes())
-            .execute(&self.pool)
-            .await
-            .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
-
-        Ok(())
+        ops::ensure_writable(self.read_only, "put")?;
+        ops::put(&mut *self.pool.acquire().await?, &self.ctx, key, &value, None).await
     }
 
     async fn get(&self, key: &str) -> agentdb::Result<Option<Value>> {
-        let query = match self.backend_type {
-            #[cfg(feature = "mysql")]
-            BackendType::Mysql => "SELECT value FROM kv_store WHERE `key` = ?",
-            _ => "SELECT value FROM kv_store WHERE key = ?",
-        };
-
-        let row: Option<AnyRow> = sqlx::query(query)
-            .bind(key)
-            .fetch_optional(&self.pool)
-            .await
-            .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
-
-        match row {
-            Some(row) => {
-                let bytes: Vec<u8> = row
-                    .try_get(0)
-                    .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
-                Ok(Some(Value::new(bytes)))
-            }
-            None => Ok(None),
-        }
+        ops::get(&mut *self.read_pool().acquire().await?, &self.ctx, key).await
     }
 
     async fn delete(&self, key: &str) -> agentdb::Result<()> {
-        let query = match self.backend_type {
-            #[cfg(feature = "mysql")]
-            BackendType::Mysql => "DELETE FROM kv_store WHERE `key` = ?",
-            _ => "DELETE FROM kv_store WHERE key = ?",
-        };
-
-        let result = sqlx::query(query)
-            .bind(key)
-            .execute(&self.pool)
-            .await
-            .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
+        ops::ensure_writable(self.read_only, "delete")?;
+        ops::delete(&mut *self.pool.acquire().await?, &self.ctx, key).await
+    }
 
-        if result.rows_affected() == 0 {
-            return Err(agentdb::AgentDbError::NotFound(key.to_string()));
-        }
+    async fn exists(&self, key: &str) -> agentdb::Result<bool> {
+        ops::exists(&mut *self.read_pool().acquire().await?, &self.ctx, key).await
+    }
 
-        Ok(())
+    async fn query(&self, query_str: &str, params: Vec<Value>) -> agentdb::Result<QueryResult> {
+        ops::ensure_query_allowed(self.read_only, self.ctx.backend_type, query_str)?;
+        let params: Vec<SqlValue> = params.into_iter().map(SqlValue::from).collect();
+        let mut conn = self.query_pool(query_str).acquire().await?;
+        let result = ops::query(&mut *conn, self.ctx.backend_type, query_str, &params).await?;
+        Ok(result.into_agent_result())
     }
 
-    async fn exists(&self, key: &str) -> agentdb::Result<bool> {
-        let query = match self.backend_type {
-            #[cfg(feature = "mysql")]
-            BackendType::Mysql => "SELECT 1 FROM kv_store WHERE `key` = ? LIMIT 1",
-            _ => "SELECT 1 FROM kv_store WHERE key = ? LIMIT 1",
-        };
+    async fn scan(&self, prefix: &str) -> agentdb::Result<ScanResult> {
+        ops::scan(&mut *self.read_pool().acquire().await?, &self.ctx, prefix).await
+    }
 
-        let row: Option<AnyRow> = sqlx::query(query)
-            .bind(key)
-            .fetch_optional(&self.pool)
+    async fn begin(&self) -> agentdb::Result<Box<dyn Transaction>> {
+        let tx = self
+            .transaction()
             .await
-            .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
+            .map_err(|e| agentdb::AgentDbError::Transaction(e.to_string()))?;
+        Ok(Box::new(tx))
+    }
 
-        Ok(row.is_some())
+    async fn close(&self) -> agentdb::Result<()> {
+        if let Some(sweeper) = &self.sweeper {
+            sweeper.stop();
+        }
+        self.pool.close().await;
+        if let Some(replica) = &self.replica {
+            replica.close().await;
+        }
+        Ok(())
     }
+}
 
-    async fn query(&self, query_str: &str, _params: Vec<Value>) -> agentdb::Result<QueryResult> {
-        // Check if this is a SELECT query
-        let is_select = query_str.trim().to_uppercase().starts_with("SELECT");
+/// Reads that bypass the read replica, returned by [`SqlBackend::primary`]
+///
+/// Without a configured replica these behave exactly like the
+/// corresponding [`SqlBackend`] methods.
+pub struct PrimaryReads<'a> {
+    backend: &'a SqlBackend,
+}
 
-        if is_select {
-            let rows: Vec<AnyRow> = sqlx::query(query_str)
-                .fetch_all(&self.pool)
-                .await
-                .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
+impl PrimaryReads<'_> {
+    /// Get a value from the primary
+    pub async fn get(&self, key: &str) -> agentdb::Result<Option<Value>> {
+        let mut conn = self.backend.pool.acquire().await?;
+        ops::get(&mut *conn, &self.backend.ctx, key).await
+    }
 
-            let agent_rows: Vec<Row> = rows
-                .into_iter()
-                .map(|row| self.convert_row(row))
-                .collect::<Result<Vec<_>>>()
-                .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
+    /// Get a value and its metadata from the primary
+    pub async fn get_with_metadata(&self, key: &str) -> Result<Option<(Value, EntryMetadata)>> {
+        let mut conn = self.backend.pool.acquire().await?;
+        ops::get_with_metadata(&mut *conn, &self.backend.ctx, key).await
+    }
 
-            Ok(QueryResult::new(agent_rows, 0))
-        } else {
-            let result = sqlx::query(query_str)
-                .execute(&self.pool)
-                .await
-                .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
+    /// Check on the primary whether a key exists
+    pub async fn exists(&self, key: &str) -> agentdb::Result<bool> {
+        let mut conn = self.backend.pool.acquire().await?;
+        ops::exists(&mut *conn, &self.backend.ctx, key).await
+    }
 
-            Ok(QueryResult::new(Vec::new(), result.rows_affected() as usize))
-        }
+    /// Scan keys by prefix on the primary
+    pub async fn scan(&self, prefix: &str) -> agentdb::Result<ScanResult> {
+        let mut conn = self.backend.pool.acquire().await?;
+        ops::scan(&mut *conn, &self.backend.ctx, prefix).await
     }
 
-    async fn scan(&self, prefix: &str) -> agentdb::Result<ScanResult> {
-        let (query, pattern) = match self.backend_type {
-            #[cfg(feature = "mysql")]
-            BackendType::Mysql => (
-                "SELECT `key` FROM kv_store WHERE `key` LIKE ? ORDER BY `key`",
-                format!("{}%", prefix),
-            ),
-            _ => (
-                "SELECT key FROM kv_store WHERE key LIKE ? ORDER BY key",
-                format!("{}%", prefix),
-            ),
-        };
+    /// Stream entries under `prefix` from the primary
+    pub async fn scan_entries(&self, prefix: &str) -> Result<EntryStream> {
+        let conn = self.backend.pool.acquire().await?;
+        let options = ScanOptions::new().prefix(prefix);
+        let (query, params) = ops::entries_query(&self.backend.ctx, &options);
+        Ok(EntryStream::spawn(conn, query, params))
+    }
 
-        let rows: Vec<AnyRow> = sqlx::query(query)
-            .bind(&pattern)
-            .fetch_all(&self.pool)
-            .await
-            .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))?;
+    /// Read one page of keys on the primary
+    pub async fn scan_page(&self, options: &ScanOptions) -> Result<ScanPage> {
+        let mut conn = self.backend.pool.acquire().await?;
+        ops::scan_page(&mut *conn, &self.backend.ctx, options).await
+    }
 
-        let keys: Vec<String> = rows
-            .into_iter()
-            .map(|row| {
-                row.try_get(0)
-                    .map_err(|e| agentdb::AgentDbError::Backend(e.to_string()))
-            })
-            .collect::<std::result::Result<Vec<_>, _>>()?;
+    /// Read one page of a delimited listing on the primary
+    pub async fn list_page(&self, options: &ScanOptions, delimiter: &str) -> Result<ListPage> {
+        let mut conn = self.backend.pool.acquire().await?;
+        ops::list_page(&mut conn, &self.backend.ctx, options, delimiter).await
+    }
 
-        Ok(ScanResult::new(keys))
+    /// Execute a query on the primary, see [`AgentDB::query`]
+    ///
+    /// Params bind as text; see [`SqlValue`]'s `From<Value>` for PostgreSQL.
+    pub async fn query(
+        &self,
+        query_str: &str,
+        params: Vec<Value>,
+    ) -> agentdb::Result<QueryResult> {
+        ops::ensure_query_allowed(
+            self.backend.read_only,
+            self.backend.ctx.backend_type,
+            query_str,
+        )?;
+        let params: Vec<SqlValue> = params.into_iter().map(SqlValue::from).collect();
+        let mut conn = self.backend.pool.acquire().await?;
+        let result =
+            ops::query(&mut *conn, self.backend.ctx.backend_type, query_str, &params).await?;
+        Ok(result.into_agent_result())
     }
 
-    async fn begin(&self) -> agentdb::Result<Box<dyn Transaction>> {
-        Err(agentdb::AgentDbError::Unsupported(
-            "Transactions not yet implemented for SQL backend".to_string(),
-        ))
+    /// Execute a query on the primary, see [`SqlBackend::query_typed`]
+    pub async fn query_typed(
+        &self,
+        query_str: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<SqlQueryResult> {
+        ops::ensure_query_allowed(
+            self.backend.read_only,
+            self.backend.ctx.backend_type,
+            query_str,
+        )?;
+        let mut conn = self.backend.pool.acquire().await?;
+        Ok(ops::query(&mut *conn, self.backend.ctx.backend_type, query_str, &params).await?)
     }
 
-    async fn close(&self) -> agentdb::Result<()> {
-        self.pool.close().await;
-        Ok(())
+    /// Execute a SELECT on the primary, see [`SqlBackend::query_as`]
+    pub async fn query_as<T: DeserializeOwned>(
+        &self,
+        query_str: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<T>> {
+        self.query_typed(query_str, params)
+            .await?
+            .rows
+            .into_iter()
+            .map(SqlRow::decode)
+            .collect()
     }
 }
 
@@ -452,6 +1535,67 @@ impl AgentDB for SqlBackend {
 mod tests {
     use super::*;
 
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn test_sqlite_from_url() {
+        let path = |url| match SqlBackendConfig::from_url(url) {
+            Ok(SqlBackendConfig::Sqlite(path)) => path,
+            other => panic!("unexpected config for {}: {:?}", url, other),
+        };
+
+        assert_eq!(path("sqlite::memory:"), ":memory:");
+        assert_eq!(path("sqlite://:memory:"), ":memory:");
+        assert_eq!(path("sqlite::memory:shared"), ":memory:shared");
+        assert_eq!(path("sqlite:agent.db"), "agent.db");
+        assert_eq!(path("sqlite://agent.db"), "agent.db");
+        assert_eq!(path("sqlite:///var/lib/agent.db"), "/var/lib/agent.db");
+        assert_eq!(path("SQLITE:agent.db"), "agent.db");
+
+        for url in ["sqlite:", "sqlite:agent.db?mode=ro", "agent.db", "redis://localhost"] {
+            assert!(
+                matches!(SqlBackendConfig::from_url(url), Err(SqlError::Connection(_))),
+                "{} was accepted",
+                url
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "postgres", feature = "mysql"))]
+    fn test_with_statement_cache() {
+        let url = "postgres://localhost/db".to_string();
+        assert_eq!(with_statement_cache(url.clone(), None), url);
+        assert_eq!(
+            with_statement_cache(url, Some(50)),
+            "postgres://localhost/db?statement-cache-capacity=50"
+        );
+        assert_eq!(
+            with_statement_cache("mysql://localhost/db?ssl-mode=DISABLED".into(), Some(0)),
+            "mysql://localhost/db?ssl-mode=DISABLED&statement-cache-capacity=0"
+        );
+    }
+
+    #[test]
+    fn test_server_from_url() {
+        for url in ["postgres://u:p@localhost/db", "postgresql://localhost/db"] {
+            match SqlBackendConfig::from_url(url) {
+                #[cfg(feature = "postgres")]
+                Ok(SqlBackendConfig::Postgres(parsed)) => assert_eq!(parsed, url),
+                #[cfg(not(feature = "postgres"))]
+                Err(SqlError::Connection(msg)) => assert!(msg.contains("`postgres` cargo feature")),
+                other => panic!("unexpected config for {}: {:?}", url, other),
+            }
+        }
+
+        match SqlBackendConfig::from_url("mysql://u:p@localhost/db") {
+            #[cfg(feature = "mysql")]
+            Ok(SqlBackendConfig::Mysql(parsed)) => assert_eq!(parsed, "mysql://u:p@localhost/db"),
+            #[cfg(not(feature = "mysql"))]
+            Err(SqlError::Connection(msg)) => assert!(msg.contains("`mysql` cargo feature")),
+            other => panic!("unexpected config for mysql: {:?}", other),
+        }
+    }
+
     #[tokio::test]
     #[cfg(feature = "sqlite")]
     async fn test_sqlite_operations() {
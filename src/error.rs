@@ -22,6 +22,12 @@ pub enum SqlError {
     #[error("Query error: {0}")]
     Query(String),
 
+    #[error("Read-only backend refuses {0}")]
+    ReadOnly(String),
+
+    #[error("Value of key '{0}' is not an integer")]
+    NotAnInteger(String),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
@@ -37,6 +43,12 @@ impl From<SqlError> for AgentDbError {
         match err {
             SqlError::Connection(msg) => AgentDbError::Connection(msg),
             SqlError::Query(msg) => AgentDbError::Backend(msg),
+            SqlError::ReadOnly(op) => {
+                AgentDbError::InvalidOperation(format!("{} on a read-only backend", op))
+            }
+            SqlError::NotAnInteger(key) => {
+                AgentDbError::InvalidOperation(format!("value of '{}' is not an integer", key))
+            }
             SqlError::Serialization(e) => AgentDbError::Serialization(e.to_string()),
             SqlError::Io(e) => AgentDbError::Io(e),
             SqlError::AgentDb(e) => e,
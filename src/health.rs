@@ -0,0 +1,143 @@
+//! Liveness checks and connection pool statistics
+//!
+//! [`SqlBackend::ping`](crate::SqlBackend::ping),
+//! [`pool_stats`](crate::SqlBackend::pool_stats) and
+//! [`health`](crate::SqlBackend::health) are meant for readiness probes and
+//! for spotting pool exhaustion: a pool whose `in_use` equals
+//! `max_connections` while `waiting` grows cannot keep up.
+
+use crate::{backend::BackendType, error::Result};
+use sqlx::{
+    Any, AnyPool,
+    pool::{PoolConnection, PoolOptions},
+};
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+/// Snapshot of a connection pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Open connections, idle or in use
+    pub size: u32,
+
+    /// Open connections not currently in use
+    pub idle: u32,
+
+    /// Connections handed out to callers
+    ///
+    /// SQLx returns released connections to the pool in the background, so
+    /// this may briefly include a connection that was just dropped.
+    pub in_use: u32,
+
+    /// Callers waiting for a connection
+    pub waiting: usize,
+
+    /// Upper bound on `size`
+    pub max_connections: u32,
+}
+
+/// Result of [`SqlBackend::health`](crate::SqlBackend::health)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Database the backend is connected to
+    pub backend_type: BackendType,
+
+    /// Round-trip time of a trivial query on the primary
+    pub latency: Duration,
+
+    /// Highest applied version of the built-in schema
+    pub schema_version: Option<i64>,
+
+    /// Primary pool statistics
+    pub pool: PoolStats,
+
+    /// Read replica round-trip time and pool statistics, if configured
+    pub replica: Option<(Duration, PoolStats)>,
+}
+
+/// Connection pool that counts callers waiting for a connection
+///
+/// SQLx only reports open and idle connections, so every acquire goes
+/// through here to track the rest. Clones share the pool and the count.
+#[derive(Clone)]
+pub(crate) struct CountedPool {
+    inner: AnyPool,
+    waiting: Arc<AtomicUsize>,
+}
+
+impl CountedPool {
+    pub async fn connect(
+        options: PoolOptions<Any>,
+        url: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            inner: options.connect(url).await?,
+            waiting: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub async fn acquire(&self) -> Result<PoolConnection<Any>> {
+        let _waiting = Waiting::new(&self.waiting);
+        Ok(self.inner.acquire().await?)
+    }
+
+    pub async fn begin(&self) -> Result<sqlx::Transaction<'static, Any>> {
+        let _waiting = Waiting::new(&self.waiting);
+        Ok(self.inner.begin().await?)
+    }
+
+    /// Time a trivial query, failing after `timeout`
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration> {
+        let started = tokio::time::Instant::now();
+        let round_trip = async {
+            let mut conn = self.acquire().await?;
+            sqlx::query("SELECT 1").execute(&mut *conn).await?;
+            Ok::<_, crate::SqlError>(())
+        };
+
+        match tokio::time::timeout(timeout, round_trip).await {
+            Ok(result) => result.map(|_| started.elapsed()),
+            Err(_) => Err(crate::SqlError::Connection(format!(
+                "Ping timed out after {:?}",
+                timeout
+            ))),
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let size = self.inner.size();
+        let idle = u32::try_from(self.inner.num_idle()).unwrap_or(u32::MAX);
+        PoolStats {
+            size,
+            idle,
+            in_use: size.saturating_sub(idle),
+            waiting: self.waiting.load(Ordering::Relaxed),
+            max_connections: self.inner.options().get_max_connections(),
+        }
+    }
+
+    pub async fn close(&self) {
+        self.inner.close().await;
+    }
+}
+
+/// Counts one waiting caller for as long as it is alive
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
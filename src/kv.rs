@@ -0,0 +1,253 @@
+//! Key-value types beyond the [`AgentDB`](agentdb::AgentDB) trait
+//!
+//! Every write to a key bumps its version, starting at 1 when the key is
+//! created. Conditional writes compare against that version to detect
+//! concurrent changes without holding a transaction open.
+
+use crate::{SqlError, error::Result, ops, value::SqlValue};
+use agentdb::Value;
+use chrono::{DateTime, Utc};
+use futures_core::Stream;
+use sqlx::{Any, pool::PoolConnection};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+/// A value together with its current version
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionedValue {
+    pub value: Value,
+
+    /// Incremented by every write; restarts at 1 after a delete
+    pub version: i64,
+}
+
+/// Outcome of a conditional write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CasOutcome {
+    /// The condition held; `version` is the key's version after the write,
+    /// or the version that was deleted
+    Applied { version: i64 },
+
+    /// The condition failed; `current` is the key's version, `None` if the
+    /// key is missing or expired
+    Conflict { current: Option<i64> },
+}
+
+impl CasOutcome {
+    /// Did the write happen?
+    pub fn is_applied(&self) -> bool {
+        matches!(self, CasOutcome::Applied { .. })
+    }
+}
+
+/// Direction of a key scan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanOrder {
+    /// Smallest key first (the default)
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// Filters, bounds and paging for [`SqlBackend::scan_page`](crate::SqlBackend::scan_page)
+///
+/// Keys are compared byte-wise on every backend, so pages come back in the
+/// same order on SQLite, PostgreSQL and MySQL.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub(crate) prefix: String,
+    pub(crate) start: Option<String>,
+    pub(crate) end: Option<String>,
+    pub(crate) limit: Option<usize>,
+    pub(crate) after: Option<String>,
+    pub(crate) order: ScanOrder,
+    pub(crate) modified_since: Option<DateTime<Utc>>,
+}
+
+impl ScanOptions {
+    /// Every live key, ascending, in a single page
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keys starting with `prefix`
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Only keys greater than or equal to `key`
+    pub fn start(mut self, key: impl Into<String>) -> Self {
+        self.start = Some(key.into());
+        self
+    }
+
+    /// Only keys strictly less than `key`
+    pub fn end(mut self, key: impl Into<String>) -> Self {
+        self.end = Some(key.into());
+        self
+    }
+
+    /// Return at most `limit` keys per page (at least 1)
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit.max(1));
+        self
+    }
+
+    /// Only keys whose value was written at or after `time`
+    ///
+    /// Times come from the clock of the writing client, at millisecond
+    /// precision.
+    pub fn modified_since(mut self, time: DateTime<Utc>) -> Self {
+        self.modified_since = Some(time);
+        self
+    }
+
+    /// Scan direction (default: [`ScanOrder::Ascending`])
+    pub fn order(mut self, order: ScanOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Continue after the page that returned `cursor`
+    ///
+    /// The other options must match the ones used for that page.
+    pub fn after(mut self, cursor: ScanCursor) -> Self {
+        self.after = Some(cursor.0);
+        self
+    }
+}
+
+/// One page of a key scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanPage {
+    /// Keys in scan order
+    pub keys: Vec<String>,
+
+    /// Position to continue from, `None` on the last page
+    pub next: Option<ScanCursor>,
+}
+
+/// One page of a delimited listing, see
+/// [`SqlBackend::list_page`](crate::SqlBackend::list_page)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListPage {
+    /// Keys directly under the prefix, in scan order
+    pub keys: Vec<String>,
+
+    /// Distinct key prefixes one level down, each ending with the delimiter
+    pub common_prefixes: Vec<String>,
+
+    /// Position to continue from, `None` on the last page
+    pub next: Option<ScanCursor>,
+}
+
+/// Opaque position in a key scan
+///
+/// Converts to and from a string with [`Display`](std::fmt::Display) and
+/// [`FromStr`](std::str::FromStr), e.g. to hand it to an API client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ScanCursor(pub(crate) String);
+
+impl std::fmt::Display for ScanCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.bytes().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+impl std::str::FromStr for ScanCursor {
+    type Err = SqlError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            || SqlError::Query(format!("Invalid scan cursor '{}'", s));
+        let bytes = s
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| {
+                let pair =
+                    std::str::from_utf8(pair).ok().filter(|p| p.len() == 2)?;
+                u8::from_str_radix(pair, 16).ok()
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        String::from_utf8(bytes).map(ScanCursor).map_err(|_| invalid())
+    }
+}
+
+/// Stored properties of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryMetadata {
+    /// Current version, see [`VersionedValue::version`]
+    pub version: i64,
+
+    /// Length of the value in bytes
+    pub size: usize,
+
+    /// When the entry expires, `None` if it has no TTL
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// When the key was first written
+    pub created_at: DateTime<Utc>,
+
+    /// When the value was last written
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A key, its value and metadata, as yielded by [`EntryStream`]
+pub type Entry = (String, Value, EntryMetadata);
+
+/// Rows buffered ahead of the consumer
+const ENTRY_BUFFER: usize = 64;
+
+/// Entries of a scan, read from the database as they are consumed
+///
+/// Returned by [`SqlBackend::scan_entries`](crate::SqlBackend::scan_entries).
+/// A background task runs the query on its own pooled connection and hands
+/// rows over through a small buffer, so memory use does not grow with the
+/// number of entries. Dropping the stream stops the query and returns the
+/// connection to the pool.
+pub struct EntryStream {
+    rows: mpsc::Receiver<Result<Entry>>,
+}
+
+impl EntryStream {
+    /// Run `query` on `conn` in the background
+    pub(crate) fn spawn(
+        mut conn: PoolConnection<Any>,
+        query: String,
+        params: Vec<SqlValue>,
+    ) -> Self {
+        let (tx, rows) = mpsc::channel(ENTRY_BUFFER);
+        tokio::spawn(async move {
+            let mut fetch = ops::bind_params(sqlx::query(&query), &params)
+                .fetch(&mut *conn);
+            while let Some(row) =
+                std::future::poll_fn(|cx| fetch.as_mut().poll_next(cx)).await
+            {
+                let entry =
+                    row.map_err(SqlError::from).and_then(ops::decode_entry);
+                let failed = entry.is_err();
+                // The receiver is gone once the stream is dropped
+                if tx.send(entry).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Self { rows }
+    }
+}
+
+impl Stream for EntryStream {
+    type Item = Result<Entry>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.get_mut().rows.poll_recv(cx)
+    }
+}
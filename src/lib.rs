@@ -33,7 +33,28 @@
 
 pub mod backend;
 pub mod error;
+pub mod health;
+pub mod kv;
+pub mod migrate;
+mod ops;
 pub mod schema;
+mod script;
+pub mod transaction;
+mod ttl;
+pub mod value;
 
-pub use backend::{SqlBackend, SqlBackendConfig};
+pub use backend::{
+    BackendType, DATABASE_URL_ENV, PrimaryReads, SqlBackend, SqlBackendConfig,
+    SqlBackendOptions,
+};
+#[cfg(feature = "sqlite")]
+pub use backend::{JournalMode, SqlitePragmas, Synchronous};
 pub use error::{Result, SqlError};
+pub use health::{HealthReport, PoolStats};
+pub use kv::{
+    CasOutcome, Entry, EntryMetadata, EntryStream, ListPage, ScanCursor,
+    ScanOptions, ScanOrder, ScanPage, VersionedValue,
+};
+pub use migrate::{Migration, MigrationMode, MigrationSet, MigrationStatus};
+pub use transaction::SqlTransaction;
+pub use value::{SqlColumn, SqlQueryResult, SqlRow, SqlType, SqlValue};
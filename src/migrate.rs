@@ -0,0 +1,854 @@
+//! Versioned schema migrations
+//!
+//! Migrations are grouped into named [`MigrationSet`]s. The built-in
+//! key-value schema is the `agentsql` set, with its steps under
+//! `migrations/<dialect>/`; applications can register their own sets for
+//! their tables through
+//! [`SqlBackendOptions::migration_set`](crate::SqlBackendOptions::migration_set).
+//!
+//! Applied steps are recorded per set in the `schema_migrations` ledger
+//! together with a checksum of their SQL, so a step runs exactly once and
+//! the backend refuses to start when an applied step has been edited
+//! afterwards.
+
+use crate::{
+    SqlError,
+    backend::{BackendType, SqlContext},
+    error::Result,
+    script::{self, TokenKind},
+};
+use sha2::{Digest, Sha256};
+use sqlx::{AnyConnection, Connection, Row as SqlxRow};
+use std::borrow::Cow;
+
+/// Name under which the built-in schema is recorded in the ledger
+pub(crate) const BUILTIN_SET: &str = "agentsql";
+
+/// Tables and indexes of the built-in schema that get the table prefix
+const BUILTIN_OBJECTS: &[&str] = &[
+    "fs_inode",
+    "fs_dentry",
+    "fs_data",
+    "fs_symlink",
+    "kv_store",
+    "kv_store_binary",
+    "kv_store_millis",
+    "tool_calls",
+    "idx_fs_dentry_parent",
+    "idx_fs_data_ino_offset",
+    "idx_kv_store_created_at",
+    "idx_kv_store_updated_at",
+    "idx_kv_store_expires_at",
+    "idx_tool_calls_name",
+    "idx_tool_calls_started_at",
+];
+
+/// A single numbered migration step
+///
+/// A step carries SQL for each dialect it supports. Running a set against
+/// a backend whose dialect a step does not cover fails with
+/// [`SqlError::Migration`].
+///
+/// # Examples
+///
+/// ```
+/// use agentsql::Migration;
+///
+/// let step = Migration::new(1, "create_notes")
+///     .sqlite("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)")
+///     .postgres("CREATE TABLE notes (id BIGSERIAL PRIMARY KEY, body TEXT)");
+/// assert_eq!(step.version(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Migration {
+    version: i64,
+    description: Cow<'static, str>,
+    sqlite: Option<Cow<'static, str>>,
+    postgres: Option<Cow<'static, str>>,
+    mysql: Option<Cow<'static, str>>,
+}
+
+impl Migration {
+    /// Create a step without any SQL yet
+    ///
+    /// `version` must be positive and increase within a set.
+    pub fn new(
+        version: i64,
+        description: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Self {
+            version,
+            description: description.into(),
+            sqlite: None,
+            postgres: None,
+            mysql: None,
+        }
+    }
+
+    /// SQL run on SQLite; may contain several statements
+    pub fn sqlite(mut self, sql: impl Into<Cow<'static, str>>) -> Self {
+        self.sqlite = Some(sql.into());
+        self
+    }
+
+    /// SQL run on PostgreSQL; may contain several statements
+    pub fn postgres(mut self, sql: impl Into<Cow<'static, str>>) -> Self {
+        self.postgres = Some(sql.into());
+        self
+    }
+
+    /// SQL run on MySQL; may contain several statements
+    pub fn mysql(mut self, sql: impl Into<Cow<'static, str>>) -> Self {
+        self.mysql = Some(sql.into());
+        self
+    }
+
+    /// Version number of this step
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    /// Human-readable description recorded in the ledger
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// SQL of this step for `backend_type`, if it has any
+    pub(crate) fn sql(&self, backend_type: BackendType) -> Option<&str> {
+        match backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => self.sqlite.as_deref(),
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => self.postgres.as_deref(),
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => self.mysql.as_deref(),
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 of migration SQL
+fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// A named, independently versioned list of migration steps
+///
+/// # Examples
+///
+/// ```
+/// use agentsql::{Migration, MigrationSet};
+///
+/// let set = MigrationSet::new("notes").migration(
+///     Migration::new(1, "create_notes")
+///         .sqlite("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)"),
+/// );
+/// assert_eq!(set.name(), "notes");
+/// ```
+#[derive(Debug, Clone)]
+pub struct MigrationSet {
+    name: Cow<'static, str>,
+    migrations: Vec<Migration>,
+}
+
+impl MigrationSet {
+    /// Create an empty set
+    ///
+    /// The name identifies the set in the ledger and must not change once
+    /// the set has been applied anywhere.
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        Self { name: name.into(), migrations: Vec::new() }
+    }
+
+    /// Append a step
+    pub fn migration(mut self, migration: Migration) -> Self {
+        self.migrations.push(migration);
+        self
+    }
+
+    /// Name of this set
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Steps of this set, in the order they were added
+    pub fn migrations(&self) -> &[Migration] {
+        &self.migrations
+    }
+
+    /// Check that versions are positive and strictly increasing
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(SqlError::Migration(
+                "Migration set name must not be empty".to_string(),
+            ));
+        }
+
+        let mut previous = 0;
+        for migration in &self.migrations {
+            if migration.version <= previous {
+                return Err(SqlError::Migration(format!(
+                    "Migration {} of set '{}' must be greater than {}",
+                    migration.version, self.name, previous
+                )));
+            }
+            previous = migration.version;
+        }
+
+        Ok(())
+    }
+}
+
+/// Current and pending schema versions of a migration set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationStatus {
+    /// Highest applied version, or `None` for an empty database
+    pub current_version: Option<i64>,
+
+    /// Highest version known to this build
+    pub latest_version: i64,
+
+    /// Versions that have not been applied yet, in order
+    pub pending: Vec<i64>,
+}
+
+impl MigrationStatus {
+    /// Is the database at the latest known version?
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// The built-in key-value schema
+pub(crate) fn builtin() -> MigrationSet {
+    MigrationSet::new(BUILTIN_SET)
+        .migration(
+            Migration::new(1, "initial")
+                .sqlite(include_str!("../migrations/sqlite/0001_initial.sql"))
+                .postgres(include_str!(
+                    "../migrations/postgres/0001_initial.sql"
+                ))
+                .mysql(include_str!("../migrations/mysql/0001_initial.sql")),
+        )
+        .migration(
+            Migration::new(2, "kv_value_binary")
+                .sqlite(include_str!(
+                    "../migrations/sqlite/0002_kv_value_binary.sql"
+                ))
+                .postgres(include_str!(
+                    "../migrations/postgres/0002_kv_value_binary.sql"
+                ))
+                .mysql(include_str!(
+                    "../migrations/mysql/0002_kv_value_binary.sql"
+                )),
+        )
+        .migration(
+            Migration::new(3, "kv_expires_at")
+                .sqlite(include_str!(
+                    "../migrations/sqlite/0003_kv_expires_at.sql"
+                ))
+                .postgres(include_str!(
+                    "../migrations/postgres/0003_kv_expires_at.sql"
+                ))
+                .mysql(include_str!(
+                    "../migrations/mysql/0003_kv_expires_at.sql"
+                )),
+        )
+        .migration(
+            Migration::new(4, "kv_version")
+                .sqlite(include_str!(
+                    "../migrations/sqlite/0004_kv_version.sql"
+                ))
+                .postgres(include_str!(
+                    "../migrations/postgres/0004_kv_version.sql"
+                ))
+                .mysql(include_str!(
+                    "../migrations/mysql/0004_kv_version.sql"
+                )),
+        )
+        .migration(
+            Migration::new(5, "kv_timestamps_millis")
+                .sqlite(include_str!(
+                    "../migrations/sqlite/0005_kv_timestamps_millis.sql"
+                ))
+                .postgres(include_str!(
+                    "../migrations/postgres/0005_kv_timestamps_millis.sql"
+                ))
+                .mysql(include_str!(
+                    "../migrations/mysql/0005_kv_timestamps_millis.sql"
+                )),
+        )
+        .migration(
+            Migration::new(6, "kv_key_byte_order")
+                .sqlite(include_str!(
+                    "../migrations/sqlite/0006_kv_key_byte_order.sql"
+                ))
+                .postgres(include_str!(
+                    "../migrations/postgres/0006_kv_key_byte_order.sql"
+                ))
+                .mysql(include_str!(
+                    "../migrations/mysql/0006_kv_key_byte_order.sql"
+                )),
+        )
+}
+
+/// What [`SqlBackend`](crate::SqlBackend) does with migrations on connect
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MigrationMode {
+    /// Apply pending migrations (the default)
+    #[default]
+    Auto,
+
+    /// Run no DDL; fail with [`SqlError::Migration`] unless every
+    /// migration set is up to date and unmodified
+    Verify,
+
+    /// Do not touch the schema at all
+    Skip,
+}
+
+/// A migration step recorded in the ledger
+struct AppliedMigration {
+    version: i64,
+    checksum: String,
+}
+
+/// Runs migration sets against a single connection
+pub(crate) struct Migrator {
+    ctx: SqlContext,
+    ledger: String,
+    target: Option<i64>,
+}
+
+impl Migrator {
+    pub fn new(ctx: SqlContext) -> Self {
+        Self { ledger: ctx.table("schema_migrations"), ctx, target: None }
+    }
+
+    /// Stop after `version` instead of applying every pending step
+    pub fn up_to(mut self, version: i64) -> Self {
+        self.target = Some(version);
+        self
+    }
+
+    /// Fail unless every set is fully applied and unmodified
+    pub async fn check(
+        &self,
+        conn: &mut AnyConnection,
+        sets: &[MigrationSet],
+    ) -> Result<()> {
+        for set in sets {
+            let status = self.status(conn, set).await?;
+            if !status.is_up_to_date() {
+                return Err(SqlError::Migration(format!(
+                    "Schema of set '{}' is behind: at version {}, expected {} (pending: {:?})",
+                    set.name,
+                    status
+                        .current_version
+                        .map_or("none".to_string(), |v| v.to_string()),
+                    status.latest_version,
+                    status.pending
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create the ledger table if it does not exist yet
+    pub async fn ensure_ledger(&self, conn: &mut AnyConnection) -> Result<()> {
+        let ledger = &self.ledger;
+        let ddl = match self.ctx.backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => format!(
+                "CREATE TABLE IF NOT EXISTS {ledger} (
+                    migration_set TEXT NOT NULL,
+                    version INTEGER NOT NULL,
+                    description TEXT NOT NULL,
+                    checksum TEXT NOT NULL,
+                    applied_at INTEGER NOT NULL,
+                    PRIMARY KEY (migration_set, version)
+                )"
+            ),
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => format!(
+                "CREATE TABLE IF NOT EXISTS {ledger} (
+                    migration_set TEXT NOT NULL,
+                    version BIGINT NOT NULL,
+                    description TEXT NOT NULL,
+                    checksum TEXT NOT NULL,
+                    applied_at BIGINT NOT NULL,
+                    PRIMARY KEY (migration_set, version)
+                )"
+            ),
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => format!(
+                "CREATE TABLE IF NOT EXISTS {ledger} (
+                    migration_set VARCHAR(255) NOT NULL,
+                    version BIGINT NOT NULL,
+                    description VARCHAR(255) NOT NULL,
+                    checksum CHAR(64) NOT NULL,
+                    applied_at BIGINT NOT NULL,
+                    PRIMARY KEY (migration_set, version)
+                ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci"
+            ),
+        };
+
+        sqlx::query(&ddl).execute(&mut *conn).await.map_err(|e| {
+            SqlError::Migration(format!(
+                "Failed to create {}: {}",
+                self.ledger, e
+            ))
+        })?;
+
+        Ok(())
+    }
+
+    /// Report the applied and pending versions of this set
+    ///
+    /// Also fails when an applied step no longer matches its checksum.
+    pub async fn status(
+        &self,
+        conn: &mut AnyConnection,
+        set: &MigrationSet,
+    ) -> Result<MigrationStatus> {
+        let applied = self.applied(conn, set).await?;
+        self.verify(set, &applied)?;
+
+        let pending = set
+            .migrations
+            .iter()
+            .filter(|m| !applied.iter().any(|a| a.version == m.version))
+            .map(|m| m.version)
+            .collect();
+
+        Ok(MigrationStatus {
+            current_version: applied.iter().map(|a| a.version).max(),
+            latest_version: set
+                .migrations
+                .iter()
+                .map(|m| m.version)
+                .max()
+                .unwrap_or(0),
+            pending,
+        })
+    }
+
+    /// Apply all pending steps of `sets`, set by set in version order
+    ///
+    /// Holds a database-wide lock while running, or SQLite's write lock
+    /// for each step, so several processes connecting at once do not race
+    /// each other.
+    pub async fn run(
+        &self,
+        conn: &mut AnyConnection,
+        sets: &[MigrationSet],
+    ) -> Result<()> {
+        self.ensure_ledger(conn).await?;
+        self.lock(conn).await?;
+        let result = self.run_locked(conn, sets).await;
+        let unlocked = self.unlock(conn).await;
+        result.and(unlocked)
+    }
+
+    async fn run_locked(
+        &self,
+        conn: &mut AnyConnection,
+        sets: &[MigrationSet],
+    ) -> Result<()> {
+        for set in sets {
+            let applied = self.applied(conn, set).await?;
+            self.verify(set, &applied)?;
+
+            if let Some(target) = self.target {
+                self.check_target(set, &applied, target)?;
+            }
+
+            for migration in &set.migrations {
+                if self.target.is_some_and(|t| migration.version > t) {
+                    break;
+                }
+                if applied.iter().any(|a| a.version == migration.version) {
+                    continue;
+                }
+                self.apply(conn, set, migration).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only known versions at or above the applied one can be targeted
+    fn check_target(
+        &self,
+        set: &MigrationSet,
+        applied: &[AppliedMigration],
+        target: i64,
+    ) -> Result<()> {
+        if !set.migrations.iter().any(|m| m.version == target) {
+            return Err(SqlError::Migration(format!(
+                "Migration set '{}' has no version {}",
+                set.name, target
+            )));
+        }
+
+        if let Some(current) = applied.iter().map(|a| a.version).max()
+            && current > target
+        {
+            return Err(SqlError::Migration(format!(
+                "Migration set '{}' is already at version {}; cannot migrate down to {}",
+                set.name, current, target
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Run a single step and record it in the ledger
+    ///
+    /// A step another process recorded since the ledger was last read is
+    /// skipped, which only happens on SQLite; see [`lock`](Self::lock).
+    async fn apply(
+        &self,
+        conn: &mut AnyConnection,
+        set: &MigrationSet,
+        migration: &Migration,
+    ) -> Result<()> {
+        let sql = self.sql(set, migration)?;
+        let prefixed = self.prefix_identifiers(set, sql);
+        let mut tx = conn.begin().await?;
+        self.lock_step(&mut tx).await?;
+        let applied = self.applied(&mut tx, set).await?;
+        if applied.iter().any(|a| a.version == migration.version) {
+            return Ok(());
+        }
+
+        for (idx, statement) in
+            script::split_statements(&prefixed, self.ctx.backend_type)
+                .into_iter()
+                .enumerate()
+        {
+            sqlx::query(statement).execute(&mut *tx).await.map_err(|e| {
+                SqlError::Migration(format!(
+                    "Failed to execute statement #{} of migration {} ({}) of set '{}': {} - Error: {}",
+                    idx,
+                    migration.version,
+                    migration.description,
+                    set.name,
+                    statement,
+                    e
+                ))
+            })?;
+        }
+
+        let insert = format!(
+            "INSERT INTO {} (migration_set, version, description, checksum, applied_at) VALUES ({}, {}, {}, {}, {})",
+            self.ledger,
+            self.ctx.backend_type.placeholder(1),
+            self.ctx.backend_type.placeholder(2),
+            self.ctx.backend_type.placeholder(3),
+            self.ctx.backend_type.placeholder(4),
+            self.ctx.backend_type.placeholder(5),
+        );
+        sqlx::query(&insert)
+            .bind(set.name())
+            .bind(migration.version)
+            .bind(migration.description())
+            .bind(checksum(sql))
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Apply the table prefix to the built-in tables and indexes
+    ///
+    /// Application sets are run verbatim. Checksums are taken over the
+    /// unprefixed SQL, so changing the prefix never invalidates a step.
+    fn prefix_identifiers<'s>(
+        &self,
+        set: &MigrationSet,
+        sql: &'s str,
+    ) -> Cow<'s, str> {
+        if self.ctx.prefix().is_empty() || set.name != BUILTIN_SET {
+            return Cow::Borrowed(sql);
+        }
+
+        let is_builtin = |name: &str| {
+            BUILTIN_OBJECTS
+                .iter()
+                .any(|builtin| builtin.eq_ignore_ascii_case(name))
+        };
+
+        let mut out = String::with_capacity(sql.len() + 64);
+        for token in script::tokenize(sql, self.ctx.backend_type) {
+            match token.kind {
+                TokenKind::Word if is_builtin(token.text) => {
+                    out.push_str(self.ctx.prefix());
+                    out.push_str(token.text);
+                }
+                // Sequence names passed as strings, as in `setval('fs_inode_ino_seq', ...)`
+                TokenKind::Quoted
+                    if token
+                        .text
+                        .strip_prefix('\'')
+                        .and_then(|t| t.strip_suffix("_seq'"))
+                        .and_then(|t| t.rsplit_once('_'))
+                        .is_some_and(|(table, _)| is_builtin(table)) =>
+                {
+                    out.push('\'');
+                    out.push_str(self.ctx.prefix());
+                    out.push_str(&token.text[1..]);
+                }
+                _ => out.push_str(token.text),
+            }
+        }
+        Cow::Owned(out)
+    }
+
+    /// SQL of `migration` for this dialect, or an error naming the gap
+    fn sql<'m>(
+        &self,
+        set: &MigrationSet,
+        migration: &'m Migration,
+    ) -> Result<&'m str> {
+        migration.sql(self.ctx.backend_type).ok_or_else(|| {
+            SqlError::Migration(format!(
+                "Migration {} ({}) of set '{}' has no SQL for {:?}",
+                migration.version,
+                migration.description,
+                set.name,
+                self.ctx.backend_type
+            ))
+        })
+    }
+
+    async fn applied(
+        &self,
+        conn: &mut AnyConnection,
+        set: &MigrationSet,
+    ) -> Result<Vec<AppliedMigration>> {
+        let query = format!(
+            "SELECT version, checksum FROM {} WHERE migration_set = {} ORDER BY version",
+            self.ledger,
+            self.ctx.backend_type.placeholder(1)
+        );
+
+        // No ledger yet means nothing has been applied
+        if !self.has_ledger(conn).await? {
+            return Ok(Vec::new());
+        }
+
+        let rows =
+            sqlx::query(&query).bind(set.name()).fetch_all(&mut *conn).await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(AppliedMigration {
+                    version: row.try_get(0)?,
+                    checksum: row.try_get(1)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn has_ledger(&self, conn: &mut AnyConnection) -> Result<bool> {
+        let query = match self.ctx.backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => {
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?"
+            }
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => {
+                "SELECT 1 FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = $1"
+            }
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => {
+                "SELECT 1 FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = ?"
+            }
+        };
+
+        Ok(sqlx::query(query)
+            .bind(&self.ledger)
+            .fetch_optional(&mut *conn)
+            .await?
+            .is_some())
+    }
+
+    /// Refuse to continue if an applied step was edited or is unknown
+    fn verify(
+        &self,
+        set: &MigrationSet,
+        applied: &[AppliedMigration],
+    ) -> Result<()> {
+        for entry in applied {
+            let Some(migration) =
+                set.migrations.iter().find(|m| m.version == entry.version)
+            else {
+                return Err(SqlError::Migration(format!(
+                    "Migration {} of set '{}' is applied but unknown to this build",
+                    entry.version, set.name
+                )));
+            };
+
+            let expected = checksum(self.sql(set, migration)?);
+            if expected != entry.checksum {
+                return Err(SqlError::Migration(format!(
+                    "Migration {} ({}) of set '{}' was modified after it was applied (checksum {} != {})",
+                    migration.version,
+                    migration.description,
+                    set.name,
+                    expected,
+                    entry.checksum
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take the database-wide migration lock
+    ///
+    /// SQLite has no lock that outlives a transaction, so there each step
+    /// takes the write lock instead, see [`lock_step`](Self::lock_step).
+    #[cfg_attr(
+        not(any(feature = "postgres", feature = "mysql")),
+        allow(unused_variables)
+    )]
+    async fn lock(&self, conn: &mut AnyConnection) -> Result<()> {
+        let lock_err = |e: sqlx::Error| {
+            SqlError::Migration(format!(
+                "Failed to acquire migration lock: {}",
+                e
+            ))
+        };
+
+        match self.ctx.backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => Ok(()),
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => {
+                sqlx::query(
+                    "SELECT pg_advisory_lock(hashtext('agentsql_migrations'))::TEXT",
+                )
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(lock_err)?;
+                Ok(())
+            }
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => {
+                let acquired: Option<i64> = sqlx::query_scalar(
+                    "SELECT GET_LOCK('agentsql_migrations', 300)",
+                )
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(lock_err)?;
+
+                // GET_LOCK returns 0 on timeout instead of failing
+                if acquired != Some(1) {
+                    return Err(SqlError::Migration(
+                        "Timed out waiting for the migration lock".to_string(),
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Take SQLite's write lock at the start of a step's transaction
+    ///
+    /// A deferred `BEGIN` only takes it at the first write, so two processes
+    /// could both read the ledger and then run the same step. A write that
+    /// touches no rows takes it up front, waiting out `busy_timeout`.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    async fn lock_step(&self, conn: &mut AnyConnection) -> Result<()> {
+        match self.ctx.backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => {
+                let query = format!("DELETE FROM {} WHERE 0", self.ledger);
+                sqlx::query(&query).execute(&mut *conn).await.map_err(
+                    |e| {
+                        SqlError::Migration(format!(
+                            "Failed to acquire migration lock: {}",
+                            e
+                        ))
+                    },
+                )?;
+                Ok(())
+            }
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => Ok(()),
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => Ok(()),
+        }
+    }
+
+    /// Release the lock taken by [`lock`](Self::lock)
+    #[cfg_attr(
+        not(any(feature = "postgres", feature = "mysql")),
+        allow(unused_variables)
+    )]
+    async fn unlock(&self, conn: &mut AnyConnection) -> Result<()> {
+        let unlock_err = |e: sqlx::Error| {
+            SqlError::Migration(format!(
+                "Failed to release migration lock: {}",
+                e
+            ))
+        };
+
+        match self.ctx.backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => Ok(()),
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => {
+                sqlx::query(
+                    "SELECT pg_advisory_unlock(hashtext('agentsql_migrations'))::TEXT",
+                )
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(unlock_err)?;
+                Ok(())
+            }
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => {
+                sqlx::query("SELECT RELEASE_LOCK('agentsql_migrations')")
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(unlock_err)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_identifiers() {
+        let migrator =
+            Migrator::new(SqlContext::new(BackendType::Postgres, "agent1_"));
+        let sql = "CREATE INDEX idx_kv_store_created_at ON kv_store(created_at);\n\
+                   SELECT setval('fs_inode_ino_seq', 1) FROM fs_inode \
+                   WHERE name = 'kv_store'; -- kv_store";
+        assert_eq!(
+            migrator.prefix_identifiers(&builtin(), sql),
+            "CREATE INDEX agent1_idx_kv_store_created_at ON agent1_kv_store(created_at);\n\
+             SELECT setval('agent1_fs_inode_ino_seq', 1) FROM agent1_fs_inode \
+             WHERE name = 'kv_store'; -- kv_store"
+        );
+
+        let other = MigrationSet::new("notes");
+        assert_eq!(migrator.prefix_identifiers(&other, sql), sql);
+
+        let unprefixed =
+            Migrator::new(SqlContext::new(BackendType::Postgres, ""));
+        assert_eq!(unprefixed.prefix_identifiers(&builtin(), sql), sql);
+    }
+}
@@ -0,0 +1,1496 @@
+//! Statement helpers shared by [`SqlBackend`](crate::SqlBackend) and
+//! [`SqlTransaction`](crate::SqlTransaction)
+//!
+//! Every helper is generic over the SQLx executor, so the same SQL runs
+//! against the pool or against an open transaction.
+
+use crate::script::TokenKind;
+use crate::{
+    SqlError,
+    backend::{BackendType, SqlContext},
+    error::Result,
+    kv::{
+        CasOutcome, Entry, EntryMetadata, ListPage, ScanCursor, ScanOptions,
+        ScanOrder, ScanPage, VersionedValue,
+    },
+    script,
+    value::{SqlColumn, SqlQueryResult, SqlRow, SqlType, SqlValue},
+};
+use agentdb::{ScanResult, Value};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    AnyConnection, Column, Executor, Row as SqlxRow, TypeInfo, ValueRef,
+    any::{Any, AnyArguments, AnyRow, AnyTypeInfo},
+    query::Query,
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+fn backend_err(e: impl std::fmt::Display) -> agentdb::AgentDbError {
+    agentdb::AgentDbError::Backend(e.to_string())
+}
+
+/// Fail with [`SqlError::ReadOnly`] if `read_only` is set
+pub(crate) fn ensure_writable(read_only: bool, operation: &str) -> Result<()> {
+    if read_only {
+        return Err(SqlError::ReadOnly(operation.to_string()));
+    }
+    Ok(())
+}
+
+/// Like [`ensure_writable`], but lets SELECTs through
+pub(crate) fn ensure_query_allowed(
+    read_only: bool,
+    backend_type: BackendType,
+    query_str: &str,
+) -> Result<()> {
+    ensure_writable(
+        read_only && !is_select(backend_type, query_str),
+        "non-SELECT query",
+    )
+}
+
+/// Current time in Unix milliseconds, the unit of the `kv_store`
+/// timestamps `created_at`, `updated_at` and `expires_at`
+pub(crate) fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// Unix milliseconds `ttl` from now
+pub(crate) fn expiry(ttl: Duration) -> i64 {
+    let ttl = i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX);
+    now_millis().saturating_add(ttl)
+}
+
+/// Condition matching entries that have not expired, comparing against
+/// the current time bound at parameter `n`
+fn live(ctx: &SqlContext, n: usize) -> String {
+    format!(
+        "(expires_at IS NULL OR expires_at > {})",
+        ctx.backend_type.placeholder(n)
+    )
+}
+
+/// Insert or replace a key-value pair, expiring at `expires_at` if set
+///
+/// Replacing bumps the version; a new key starts at version 1.
+pub(crate) async fn put<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    key: &str,
+    value: &Value,
+    expires_at: Option<i64>,
+) -> agentdb::Result<()>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let table = ctx.table("kv_store");
+    // PostgreSQL caches a statement's parameter types, so an untyped NULL
+    // bound where other calls bind an integer breaks those calls; write the
+    // NULL into the SQL instead
+    let expires = match expires_at {
+        Some(_) => ctx.backend_type.placeholder(5),
+        None => "NULL".to_string(),
+    };
+    let query = match ctx.backend_type {
+        #[cfg(feature = "sqlite")]
+        BackendType::Sqlite => format!(
+            "INSERT INTO {table} (key, value, created_at, updated_at, expires_at) VALUES (?, ?, ?, ?, {expires}) ON CONFLICT (key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at, expires_at = excluded.expires_at, version = version + 1"
+        ),
+        #[cfg(feature = "postgres")]
+        BackendType::Postgres => format!(
+            "INSERT INTO {table} (key, value, created_at, updated_at, expires_at) VALUES ($1, $2, $3, $4, {expires}) ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = EXCLUDED.updated_at, expires_at = EXCLUDED.expires_at, version = {table}.version + 1"
+        ),
+        #[cfg(feature = "mysql")]
+        BackendType::Mysql => format!(
+            "INSERT INTO {table} (`key`, value, created_at, updated_at, expires_at) VALUES (?, ?, ?, ?, {expires}) ON DUPLICATE KEY UPDATE value = VALUES(value), updated_at = VALUES(updated_at), expires_at = VALUES(expires_at), version = version + 1"
+        ),
+    };
+
+    let now = now_millis();
+    let mut statement = sqlx::query(&query)
+        .bind(key)
+        .bind(value.as_bytes())
+        .bind(now)
+        .bind(now);
+    if let Some(expires_at) = expires_at {
+        statement = statement.bind(expires_at);
+    }
+    statement.execute(executor).await.map_err(backend_err)?;
+
+    Ok(())
+}
+
+/// Fetch the value stored under `key`
+pub(crate) async fn get<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    key: &str,
+) -> agentdb::Result<Option<Value>>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let query = format!(
+        "SELECT value FROM {} WHERE {} = {} AND {}",
+        ctx.table("kv_store"),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(1),
+        live(ctx, 2)
+    );
+
+    let row: Option<AnyRow> = sqlx::query(&query)
+        .bind(key)
+        .bind(now_millis())
+        .fetch_optional(executor)
+        .await
+        .map_err(backend_err)?;
+
+    match row {
+        Some(row) => {
+            let bytes: Vec<u8> = row.try_get(0).map_err(backend_err)?;
+            Ok(Some(Value::new(bytes)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Delete `key`, returning `NotFound` when nothing live was removed
+///
+/// An expired entry counts as missing, as in `get`, but its row is purged
+/// all the same.
+pub(crate) async fn delete(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    key: &str,
+) -> agentdb::Result<()> {
+    let now = now_millis();
+    let query = format!(
+        "DELETE FROM {} WHERE {} = {} AND {}",
+        ctx.table("kv_store"),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(1),
+        live(ctx, 2)
+    );
+
+    let result = sqlx::query(&query)
+        .bind(key)
+        .bind(now)
+        .execute(&mut *conn)
+        .await
+        .map_err(backend_err)?;
+
+    if result.rows_affected() == 0 {
+        // Purge an expired row, but not one a concurrent put just wrote
+        let purge = format!(
+            "DELETE FROM {} WHERE {} = {} AND expires_at <= {}",
+            ctx.table("kv_store"),
+            ctx.backend_type.key_column(),
+            ctx.backend_type.placeholder(1),
+            ctx.backend_type.placeholder(2)
+        );
+        sqlx::query(&purge)
+            .bind(key)
+            .bind(now)
+            .execute(&mut *conn)
+            .await
+            .map_err(backend_err)?;
+        return Err(agentdb::AgentDbError::NotFound(key.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Set `key` to expire at `expires_at`, returning `NotFound` for missing
+/// or already expired keys
+pub(crate) async fn expire<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    key: &str,
+    expires_at: i64,
+) -> agentdb::Result<()>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let query = format!(
+        "UPDATE {} SET expires_at = {} WHERE {} = {} AND {}",
+        ctx.table("kv_store"),
+        ctx.backend_type.placeholder(1),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(2),
+        live(ctx, 3)
+    );
+
+    let result = sqlx::query(&query)
+        .bind(expires_at)
+        .bind(key)
+        .bind(now_millis())
+        .execute(executor)
+        .await
+        .map_err(backend_err)?;
+
+    if result.rows_affected() == 0 {
+        return Err(agentdb::AgentDbError::NotFound(key.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Delete up to `batch_size` expired entries, returning how many were removed
+#[cfg_attr(
+    not(any(feature = "sqlite", feature = "postgres")),
+    allow(unused_variables)
+)]
+pub(crate) async fn purge_expired<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    batch_size: u32,
+) -> Result<u64>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let table = ctx.table("kv_store");
+    let key = ctx.backend_type.key_column();
+    let query = match ctx.backend_type {
+        // SQLite and PostgreSQL have no DELETE ... LIMIT
+        #[cfg(feature = "sqlite")]
+        BackendType::Sqlite => format!(
+            "DELETE FROM {table} WHERE {key} IN (SELECT {key} FROM {table} WHERE expires_at <= ?1 LIMIT ?2)"
+        ),
+        #[cfg(feature = "postgres")]
+        BackendType::Postgres => format!(
+            "DELETE FROM {table} WHERE {key} IN (SELECT {key} FROM {table} WHERE expires_at <= $1 LIMIT $2)"
+        ),
+        #[cfg(feature = "mysql")]
+        BackendType::Mysql => {
+            format!("DELETE FROM {table} WHERE expires_at <= ? LIMIT ?")
+        }
+    };
+
+    let result = sqlx::query(&query)
+        .bind(now_millis())
+        .bind(i64::from(batch_size))
+        .execute(executor)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Fetch the value and version stored under `key`
+pub(crate) async fn get_versioned<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    key: &str,
+) -> Result<Option<VersionedValue>>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let query = format!(
+        "SELECT value, version FROM {} WHERE {} = {} AND {}",
+        ctx.table("kv_store"),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(1),
+        live(ctx, 2)
+    );
+
+    let row: Option<AnyRow> = sqlx::query(&query)
+        .bind(key)
+        .bind(now_millis())
+        .fetch_optional(executor)
+        .await?;
+
+    match row {
+        Some(row) => Ok(Some(VersionedValue {
+            value: Value::new(row.try_get(0)?),
+            version: row.try_get(1)?,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Version of `key`, `None` if missing or expired
+async fn current_version(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    key: &str,
+) -> Result<Option<i64>> {
+    let query = format!(
+        "SELECT version FROM {} WHERE {} = {} AND {}",
+        ctx.table("kv_store"),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(1),
+        live(ctx, 2)
+    );
+
+    Ok(sqlx::query_scalar(&query)
+        .bind(key)
+        .bind(now_millis())
+        .fetch_optional(&mut *conn)
+        .await?)
+}
+
+/// Replace the value of `key` if its version is `expected`
+pub(crate) async fn put_if_version(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    key: &str,
+    value: &Value,
+    expected: i64,
+) -> Result<CasOutcome> {
+    let backend_type = ctx.backend_type;
+    let query = format!(
+        "UPDATE {} SET value = {}, version = version + 1, updated_at = {}, expires_at = NULL WHERE {} = {} AND version = {} AND {}",
+        ctx.table("kv_store"),
+        backend_type.placeholder(1),
+        backend_type.placeholder(2),
+        backend_type.key_column(),
+        backend_type.placeholder(3),
+        backend_type.placeholder(4),
+        live(ctx, 5)
+    );
+
+    let result = sqlx::query(&query)
+        .bind(value.as_bytes())
+        .bind(now_millis())
+        .bind(key)
+        .bind(expected)
+        .bind(now_millis())
+        .execute(&mut *conn)
+        .await?;
+
+    if result.rows_affected() == 1 {
+        return Ok(CasOutcome::Applied { version: expected + 1 });
+    }
+    let current = current_version(conn, ctx, key).await?;
+    Ok(CasOutcome::Conflict { current })
+}
+
+/// Create `key` unless it exists and has not expired
+///
+/// Runs two statements; the caller provides atomicity.
+pub(crate) async fn put_if_absent(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    key: &str,
+    value: &Value,
+) -> Result<CasOutcome> {
+    let table = ctx.table("kv_store");
+    let backend_type = ctx.backend_type;
+    let key_column = backend_type.key_column();
+
+    // An expired entry counts as absent, so clear it out of the way
+    let purge = format!(
+        "DELETE FROM {table} WHERE {key_column} = {} AND expires_at <= {}",
+        backend_type.placeholder(1),
+        backend_type.placeholder(2)
+    );
+    sqlx::query(&purge)
+        .bind(key)
+        .bind(now_millis())
+        .execute(&mut *conn)
+        .await?;
+
+    let insert = match backend_type {
+        #[cfg(feature = "sqlite")]
+        BackendType::Sqlite => format!(
+            "INSERT OR IGNORE INTO {table} (key, value, created_at, updated_at) VALUES (?1, ?2, ?3, ?4)"
+        ),
+        #[cfg(feature = "postgres")]
+        BackendType::Postgres => format!(
+            "INSERT INTO {table} (key, value, created_at, updated_at) VALUES ($1, $2, $3, $4) ON CONFLICT (key) DO NOTHING"
+        ),
+        // A no-op update reports zero affected rows
+        #[cfg(feature = "mysql")]
+        BackendType::Mysql => format!(
+            "INSERT INTO {table} (`key`, value, created_at, updated_at) VALUES (?, ?, ?, ?) ON DUPLICATE KEY UPDATE `key` = `key`"
+        ),
+    };
+    let now = now_millis();
+    let result = sqlx::query(&insert)
+        .bind(key)
+        .bind(value.as_bytes())
+        .bind(now)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+
+    if result.rows_affected() == 1 {
+        return Ok(CasOutcome::Applied { version: 1 });
+    }
+    let current = current_version(conn, ctx, key).await?;
+    Ok(CasOutcome::Conflict { current })
+}
+
+/// Delete `key` if its version is `expected`
+pub(crate) async fn delete_if_version(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    key: &str,
+    expected: i64,
+) -> Result<CasOutcome> {
+    let query = format!(
+        "DELETE FROM {} WHERE {} = {} AND version = {} AND {}",
+        ctx.table("kv_store"),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(1),
+        ctx.backend_type.placeholder(2),
+        live(ctx, 3)
+    );
+
+    let result = sqlx::query(&query)
+        .bind(key)
+        .bind(expected)
+        .bind(now_millis())
+        .execute(&mut *conn)
+        .await?;
+
+    if result.rows_affected() == 1 {
+        return Ok(CasOutcome::Applied { version: expected });
+    }
+    let current = current_version(conn, ctx, key).await?;
+    Ok(CasOutcome::Conflict { current })
+}
+
+/// What every backend accepts as a counter: canonical decimal text for an
+/// `i64`, so no sign on 0, leading zeros, `+` or padding
+#[cfg(any(feature = "postgres", feature = "mysql"))]
+const COUNTER_PATTERN: &str = "^(0|-?[1-9][0-9]{0,18})$";
+
+/// `text` as a counter, by the same rule as [`COUNTER_PATTERN`]
+fn parse_counter(text: &str) -> Option<i64> {
+    text.parse().ok().filter(|n: &i64| n.to_string() == text)
+}
+
+/// Add `delta` to the integer stored under `key` and return the result
+///
+/// A missing or expired key counts as 0. Values are stored as decimal text
+/// and must be canonical (see [`COUNTER_PATTERN`]) on every backend; a sum
+/// outside `i64` is an error rather than being clamped. Runs as one upsert,
+/// except on MySQL which has no `RETURNING` and reads the result back; the
+/// caller provides a transaction, so the row stays locked in between and an
+/// overflowing SQLite sum can be rolled back.
+pub(crate) async fn increment(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    key: &str,
+    delta: i64,
+) -> Result<i64> {
+    let table = ctx.table("kv_store");
+    let now = now_millis();
+    let overflow = || {
+        SqlError::Query(format!(
+            "Incrementing '{}' by {} overflows",
+            key, delta
+        ))
+    };
+    // PostgreSQL and MySQL fail an out of range integer sum with this state
+    #[cfg(any(feature = "postgres", feature = "mysql"))]
+    let out_of_range = |err: sqlx::Error| match &err {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("22003") => {
+            overflow()
+        }
+        _ => SqlError::Sqlx(err),
+    };
+
+    let result: Option<String> = match ctx.backend_type {
+        // The round trip through INTEGER only preserves canonical integers,
+        // and clamps any outside `i64`
+        #[cfg(feature = "sqlite")]
+        BackendType::Sqlite => {
+            let expired = "(expires_at IS NOT NULL AND expires_at <= ?3)";
+            let query = format!(
+                "INSERT INTO {table} (key, value, created_at, updated_at) VALUES (?1, CAST(?2 AS BLOB), ?3, ?3) \
+                 ON CONFLICT (key) DO UPDATE SET \
+                 value = CAST(CASE WHEN {expired} THEN ?2 ELSE CAST(CAST(value AS TEXT) AS INTEGER) + ?2 END AS BLOB), \
+                 expires_at = CASE WHEN {expired} THEN NULL ELSE expires_at END, \
+                 updated_at = ?3, version = version + 1 \
+                 WHERE {expired} OR CAST(CAST(CAST(value AS TEXT) AS INTEGER) AS TEXT) = CAST(value AS TEXT) \
+                 RETURNING CAST(value AS TEXT)"
+            );
+            let sum: Option<String> = sqlx::query_scalar(&query)
+                .bind(key)
+                .bind(delta)
+                .bind(now)
+                .fetch_optional(&mut *conn)
+                .await?;
+
+            // SQLite turns an overflowing sum into a REAL instead of failing
+            if sum.as_deref().is_some_and(|sum| parse_counter(sum).is_none()) {
+                return Err(overflow());
+            }
+            sum
+        }
+        // `escape` encoding turns any bytes into text the pattern can test,
+        // and the range check only runs on text that passed it
+        #[cfg(feature = "postgres")]
+        BackendType::Postgres => {
+            let expired = format!(
+                "({table}.expires_at IS NOT NULL AND {table}.expires_at <= $3)"
+            );
+            let current = format!("encode({table}.value, 'escape')");
+            let is_int = format!(
+                "CASE WHEN {current} ~ '{COUNTER_PATTERN}' \
+                 THEN {current}::numeric BETWEEN {} AND {} ELSE false END",
+                i64::MIN,
+                i64::MAX
+            );
+            let query = format!(
+                "INSERT INTO {table} (key, value, created_at, updated_at) VALUES ($1, convert_to($2::text, 'UTF8'), $3, $3) \
+                 ON CONFLICT (key) DO UPDATE SET \
+                 value = convert_to((CASE WHEN {expired} THEN $2 ELSE {current}::bigint + $2 END)::text, 'UTF8'), \
+                 expires_at = CASE WHEN {expired} THEN NULL ELSE {table}.expires_at END, \
+                 updated_at = $3, version = {table}.version + 1 \
+                 WHERE {expired} OR {is_int} \
+                 RETURNING convert_from(value, 'UTF8')"
+            );
+            sqlx::query_scalar(&query)
+                .bind(key)
+                .bind(delta)
+                .bind(now)
+                .fetch_optional(&mut *conn)
+                .await
+                .map_err(out_of_range)?
+        }
+        // Assignments run left to right and see earlier ones, so
+        // `expires_at` is cleared last; a non-integer row is left unchanged.
+        // `IF` only evaluates the branch it takes, so the casts never see
+        // text that failed the pattern
+        #[cfg(feature = "mysql")]
+        BackendType::Mysql => {
+            let expired = "(expires_at IS NOT NULL AND expires_at <= ?)";
+            let current = "CAST(value AS CHAR)";
+            let is_int = format!(
+                "IF({current} REGEXP '{COUNTER_PATTERN}', \
+                 CAST({current} AS DECIMAL(19, 0)) BETWEEN {} AND {}, FALSE)",
+                i64::MIN,
+                i64::MAX
+            );
+            let query = format!(
+                "INSERT INTO {table} (`key`, value, created_at, updated_at) VALUES (?, CAST(? AS CHAR), ?, ?) \
+                 ON DUPLICATE KEY UPDATE \
+                 version = IF({expired} OR {is_int}, version + 1, version), \
+                 updated_at = IF({expired} OR {is_int}, VALUES(updated_at), updated_at), \
+                 value = IF({expired}, VALUES(value), IF({is_int}, CAST(CAST({current} AS SIGNED) + ? AS CHAR), value)), \
+                 expires_at = IF({expired}, NULL, expires_at)"
+            );
+            sqlx::query(&query)
+                .bind(key)
+                .bind(delta)
+                .bind(now)
+                .bind(now)
+                .bind(now)
+                .bind(now)
+                .bind(now)
+                .bind(delta)
+                .bind(now)
+                .execute(&mut *conn)
+                .await
+                .map_err(out_of_range)?;
+
+            // An unchanged row still holds its old, non-canonical value,
+            // which fails the same check as a missing `RETURNING` row
+            let select = format!("SELECT value FROM {table} WHERE `key` = ?");
+            let value: Option<Vec<u8>> = sqlx::query_scalar(&select)
+                .bind(key)
+                .fetch_optional(&mut *conn)
+                .await?;
+            value.and_then(|value| String::from_utf8(value).ok())
+        }
+    };
+
+    result
+        .as_deref()
+        .and_then(parse_counter)
+        .ok_or_else(|| SqlError::NotAnInteger(key.to_string()))
+}
+
+/// `-delta`, for decrements
+pub(crate) fn negate(key: &str, delta: i64) -> Result<i64> {
+    delta.checked_neg().ok_or_else(|| {
+        SqlError::Query(format!(
+            "Decrementing '{}' by {} overflows",
+            key, delta
+        ))
+    })
+}
+
+/// Most rows written or looked up by one batch statement
+const MAX_BATCH_ROWS: usize = 1000;
+
+/// Rows per batch statement when each row binds `params_per_row`
+/// parameters and `extra_params` more are bound once
+fn batch_rows(
+    backend_type: BackendType,
+    params_per_row: usize,
+    extra_params: usize,
+) -> usize {
+    ((backend_type.max_params() - extra_params) / params_per_row)
+        .clamp(1, MAX_BATCH_ROWS)
+}
+
+/// Comma-separated placeholders for parameters `first..first + count`
+fn placeholder_list(
+    backend_type: BackendType,
+    first: usize,
+    count: usize,
+) -> String {
+    (first..first + count)
+        .map(|n| backend_type.placeholder(n))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Keys in first-seen order without repeats
+fn unique_keys<K: AsRef<str>>(keys: &[K]) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    keys.iter().map(AsRef::as_ref).filter(|key| seen.insert(*key)).collect()
+}
+
+/// Insert or replace many key-value pairs, clearing any expiry
+///
+/// A key listed more than once keeps its last value. Runs one multi-row
+/// upsert per chunk; the caller provides atomicity.
+pub(crate) async fn put_many(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    entries: &[(String, Value)],
+) -> Result<()> {
+    // Keep the last value per key; PostgreSQL rejects an upsert that
+    // touches the same row twice
+    let last: HashMap<&str, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(idx, (key, _))| (key.as_str(), idx))
+        .collect();
+    let entries: Vec<&(String, Value)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(idx, (key, _))| last[key.as_str()] == *idx)
+        .map(|(_, entry)| entry)
+        .collect();
+
+    let table = ctx.table("kv_store");
+    let backend_type = ctx.backend_type;
+    let now = now_millis();
+    for chunk in entries.chunks(batch_rows(backend_type, 4, 0)) {
+        let rows = (0..chunk.len())
+            .map(|i| {
+                let params = placeholder_list(backend_type, 4 * i + 1, 4);
+                format!("({params}, NULL)")
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let query = match backend_type {
+            #[cfg(feature = "sqlite")]
+            BackendType::Sqlite => format!(
+                "INSERT INTO {table} (key, value, created_at, updated_at, expires_at) VALUES {rows} ON CONFLICT (key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at, expires_at = NULL, version = version + 1"
+            ),
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => format!(
+                "INSERT INTO {table} (key, value, created_at, updated_at, expires_at) VALUES {rows} ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = EXCLUDED.updated_at, expires_at = NULL, version = {table}.version + 1"
+            ),
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => format!(
+                "INSERT INTO {table} (`key`, value, created_at, updated_at, expires_at) VALUES {rows} ON DUPLICATE KEY UPDATE value = VALUES(value), updated_at = VALUES(updated_at), expires_at = NULL, version = version + 1"
+            ),
+        };
+
+        let mut statement = sqlx::query(&query);
+        for (key, value) in chunk {
+            statement = statement
+                .bind(key.as_str())
+                .bind(value.as_bytes())
+                .bind(now)
+                .bind(now);
+        }
+        statement.execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+/// Fetch the values of many keys, in the order of `keys`
+pub(crate) async fn get_many<K: AsRef<str>>(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    keys: &[K],
+) -> Result<Vec<Option<Value>>> {
+    let backend_type = ctx.backend_type;
+    let key_column = backend_type.key_column();
+    let mut found = HashMap::new();
+
+    for chunk in unique_keys(keys).chunks(batch_rows(backend_type, 1, 1)) {
+        let query = format!(
+            "SELECT {key_column}, value FROM {} WHERE {key_column} IN ({}) AND {}",
+            ctx.table("kv_store"),
+            placeholder_list(backend_type, 1, chunk.len()),
+            live(ctx, chunk.len() + 1)
+        );
+
+        let mut statement = sqlx::query(&query);
+        for key in chunk {
+            statement = statement.bind(*key);
+        }
+        let rows = statement.bind(now_millis()).fetch_all(&mut *conn).await?;
+
+        // Keys compare byte-wise on every backend (migration 6), so the
+        // stored key is exactly the one that was asked for
+        for row in rows {
+            let key: String = row.try_get(0)?;
+            let value: Vec<u8> = row.try_get(1)?;
+            found.insert(key, Value::new(value));
+        }
+    }
+
+    Ok(keys.iter().map(|key| found.get(key.as_ref()).cloned()).collect())
+}
+
+/// Delete many keys, returning how many were removed
+///
+/// Missing keys are skipped rather than reported.
+pub(crate) async fn delete_many<K: AsRef<str>>(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    keys: &[K],
+) -> Result<u64> {
+    let backend_type = ctx.backend_type;
+    let mut removed = 0;
+
+    for chunk in unique_keys(keys).chunks(batch_rows(backend_type, 1, 0)) {
+        let query = format!(
+            "DELETE FROM {} WHERE {} IN ({})",
+            ctx.table("kv_store"),
+            backend_type.key_column(),
+            placeholder_list(backend_type, 1, chunk.len())
+        );
+
+        let mut statement = sqlx::query(&query);
+        for key in chunk {
+            statement = statement.bind(*key);
+        }
+        removed += statement.execute(&mut *conn).await?.rows_affected();
+    }
+
+    Ok(removed)
+}
+
+/// Check whether `key` is present
+pub(crate) async fn exists<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    key: &str,
+) -> agentdb::Result<bool>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let query = format!(
+        "SELECT 1 FROM {} WHERE {} = {} AND {} LIMIT 1",
+        ctx.table("kv_store"),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(1),
+        live(ctx, 2)
+    );
+
+    let row: Option<AnyRow> = sqlx::query(&query)
+        .bind(key)
+        .bind(now_millis())
+        .fetch_optional(executor)
+        .await
+        .map_err(backend_err)?;
+
+    Ok(row.is_some())
+}
+
+/// List keys starting with `prefix`, ordered by key
+pub(crate) async fn scan<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    prefix: &str,
+) -> agentdb::Result<ScanResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let page = scan_page(executor, ctx, &ScanOptions::new().prefix(prefix))
+        .await
+        .map_err(backend_err)?;
+    Ok(ScanResult::new(page.keys))
+}
+
+/// Smallest string greater than every string starting with `prefix`
+///
+/// Byte-wise order of UTF-8 is code point order, so bumping the last
+/// character that has a successor gives the exclusive end of the prefix
+/// range. `None` means the range is unbounded above.
+fn prefix_successor(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            _ => char::from_u32(last as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// `WHERE` conditions on live `kv_store` rows and their bind parameters
+pub(crate) struct KeyFilter<'c> {
+    ctx: &'c SqlContext,
+    conditions: Vec<String>,
+    params: Vec<SqlValue>,
+}
+
+impl<'c> KeyFilter<'c> {
+    /// Match every entry that has not expired
+    pub fn live(ctx: &'c SqlContext) -> Self {
+        Self {
+            ctx,
+            conditions: vec![live(ctx, 1)],
+            params: vec![SqlValue::Int(now_millis())],
+        }
+    }
+
+    /// Add a condition; `condition` receives the placeholder of `param`
+    pub fn and(
+        &mut self,
+        param: SqlValue,
+        condition: impl FnOnce(String) -> String,
+    ) {
+        self.params.push(param);
+        let placeholder = self.ctx.backend_type.placeholder(self.params.len());
+        self.conditions.push(condition(placeholder));
+    }
+
+    /// Add the key bounds, prefix and cursor of `options`
+    pub fn scan_options(&mut self, options: &ScanOptions) {
+        let key = self.ctx.backend_type.key_column();
+        // A key range rather than LIKE, so `%`, `_` and `\\` in the prefix
+        // are literal. The key column collates byte-wise on every backend
+        // (migration 6), so matching is case-sensitive and uses its index
+        if !options.prefix.is_empty() {
+            let start = SqlValue::Text(options.prefix.clone());
+            self.and(start, |p| format!("{key} >= {p}"));
+            if let Some(end) = prefix_successor(&options.prefix) {
+                self.and(SqlValue::Text(end), |p| format!("{key} < {p}"));
+            }
+        }
+        if let Some(start) = &options.start {
+            self.and(SqlValue::Text(start.clone()), |p| {
+                format!("{key} >= {p}")
+            });
+        }
+        if let Some(end) = &options.end {
+            self.and(SqlValue::Text(end.clone()), |p| format!("{key} < {p}"));
+        }
+        if let Some(since) = options.modified_since {
+            let since = SqlValue::Int(since.timestamp_millis());
+            self.and(since, |p| format!("updated_at >= {p}"));
+        }
+        if let Some(after) = &options.after {
+            let op = match options.order {
+                ScanOrder::Ascending => ">",
+                ScanOrder::Descending => "<",
+            };
+            self.and(SqlValue::Text(after.clone()), |p| {
+                format!("{key} {op} {p}")
+            });
+        }
+    }
+
+    pub fn sql(&self) -> String {
+        self.conditions.join(" AND ")
+    }
+
+    pub fn params(&self) -> &[SqlValue] {
+        &self.params
+    }
+}
+
+/// `ORDER BY` and `LIMIT` clauses for a scan, fetching one extra row to
+/// tell whether another page follows
+fn scan_tail(ctx: &SqlContext, options: &ScanOptions) -> String {
+    let direction = match options.order {
+        ScanOrder::Ascending => "ASC",
+        ScanOrder::Descending => "DESC",
+    };
+    let mut tail =
+        format!("ORDER BY {} {}", ctx.backend_type.key_column(), direction);
+    if let Some(limit) = options.limit {
+        tail.push_str(&format!(" LIMIT {}", limit.saturating_add(1)));
+    }
+    tail
+}
+
+/// Split the extra row fetched by [`scan_tail`] off into a cursor
+fn into_page<T>(
+    mut items: Vec<T>,
+    options: &ScanOptions,
+    key: impl Fn(&T) -> &str,
+) -> (Vec<T>, Option<ScanCursor>) {
+    match options.limit {
+        Some(limit) if items.len() > limit => {
+            items.truncate(limit);
+            let last = items.last().map(|item| key(item).to_string());
+            (items, last.map(ScanCursor))
+        }
+        _ => (items, None),
+    }
+}
+
+/// `kv_store` columns in the order [`decode_entry`] expects
+fn entry_columns(ctx: &SqlContext) -> String {
+    // The Any driver decodes SQLite integers as 32-bit unless they are
+    // declared, which timestamps in milliseconds do not fit
+    let millis = |column: &str| match ctx.backend_type {
+        #[cfg(feature = "sqlite")]
+        BackendType::Sqlite => format!("CAST({column} AS TEXT)"),
+        #[cfg(feature = "postgres")]
+        BackendType::Postgres => column.to_string(),
+        #[cfg(feature = "mysql")]
+        BackendType::Mysql => column.to_string(),
+    };
+    format!(
+        "{}, value, version, {}, {}, {}",
+        ctx.backend_type.key_column(),
+        millis("expires_at"),
+        millis("created_at"),
+        millis("updated_at")
+    )
+}
+
+/// Query and parameters streaming every entry matching `options`, in the
+/// column order [`decode_entry`] expects
+pub(crate) fn entries_query(
+    ctx: &SqlContext,
+    options: &ScanOptions,
+) -> (String, Vec<SqlValue>) {
+    let mut filter = KeyFilter::live(ctx);
+    filter.scan_options(options);
+    let query = format!(
+        "SELECT {} FROM {} WHERE {} {}",
+        entry_columns(ctx),
+        ctx.table("kv_store"),
+        filter.sql(),
+        scan_tail(ctx, options)
+    );
+    (query, filter.params)
+}
+
+/// Decode a Unix millisecond timestamp column, `None` if NULL
+fn decode_millis(
+    row: &AnyRow,
+    i: usize,
+) -> std::result::Result<Option<DateTime<Utc>>, sqlx::Error> {
+    // The Any driver cannot decode a NULL column into an `Option`
+    Ok(match convert_value(row, i)? {
+        SqlValue::Int(millis) => DateTime::from_timestamp_millis(millis),
+        SqlValue::Text(millis) => {
+            millis.parse().ok().and_then(DateTime::from_timestamp_millis)
+        }
+        _ => None,
+    })
+}
+
+/// Decode a row selected with [`entry_columns`]
+pub(crate) fn decode_entry(row: AnyRow) -> Result<Entry> {
+    let key: String = row.try_get(0)?;
+    let value: Vec<u8> = row.try_get(1)?;
+    let metadata = EntryMetadata {
+        version: row.try_get(2)?,
+        size: value.len(),
+        expires_at: decode_millis(&row, 3)?,
+        created_at: decode_millis(&row, 4)?.unwrap_or_default(),
+        updated_at: decode_millis(&row, 5)?.unwrap_or_default(),
+    };
+    Ok((key, Value::new(value), metadata))
+}
+
+/// Fetch the value stored under `key` with its metadata
+pub(crate) async fn get_with_metadata<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    key: &str,
+) -> Result<Option<(Value, EntryMetadata)>>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let query = format!(
+        "SELECT {} FROM {} WHERE {} = {} AND {}",
+        entry_columns(ctx),
+        ctx.table("kv_store"),
+        ctx.backend_type.key_column(),
+        ctx.backend_type.placeholder(1),
+        live(ctx, 2)
+    );
+
+    let row = sqlx::query(&query)
+        .bind(key)
+        .bind(now_millis())
+        .fetch_optional(executor)
+        .await?;
+    let Some(row) = row else { return Ok(None) };
+    let (_, value, metadata) = decode_entry(row)?;
+    Ok(Some((value, metadata)))
+}
+
+/// One page of keys matching `options`
+pub(crate) async fn scan_page<'e, E>(
+    executor: E,
+    ctx: &SqlContext,
+    options: &ScanOptions,
+) -> Result<ScanPage>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let mut filter = KeyFilter::live(ctx);
+    filter.scan_options(options);
+    let query = format!(
+        "SELECT {} FROM {} WHERE {} {}",
+        ctx.backend_type.key_column(),
+        ctx.table("kv_store"),
+        filter.sql(),
+        scan_tail(ctx, options)
+    );
+
+    let keys = bind_params(sqlx::query(&query), filter.params())
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .map(|row| row.try_get(0))
+        .collect::<std::result::Result<Vec<String>, _>>()?;
+
+    let (keys, next) = into_page(keys, options, String::as_str);
+    Ok(ScanPage { keys, next })
+}
+
+/// The common prefix `key` rolls up into: `key` up to and including the
+/// first `delimiter` after `prefix`
+fn common_prefix<'k>(
+    key: &'k str,
+    prefix: &str,
+    delimiter: &str,
+) -> Option<&'k str> {
+    if delimiter.is_empty() {
+        return None;
+    }
+    let rest = key.strip_prefix(prefix)?;
+    let end = rest.find(delimiter)? + delimiter.len();
+    Some(&key[..prefix.len() + end])
+}
+
+/// Most keys one [`list_page`] fetch reads
+const LIST_SCAN_ROWS: usize = 1000;
+
+/// One page of keys and common prefixes directly under `options.prefix`
+///
+/// Every key under a common prefix sorts next to the others, so once one is
+/// seen the rest are skipped with a key bound rather than fetched. Keys and
+/// common prefixes both count towards the limit, and a cursor may point at
+/// either. Keys are fetched [`LIST_SCAN_ROWS`] at a time even without a
+/// limit, so a large subtree costs one fetch rather than all its keys.
+pub(crate) async fn list_page(
+    conn: &mut AnyConnection,
+    ctx: &SqlContext,
+    options: &ScanOptions,
+    delimiter: &str,
+) -> Result<ListPage> {
+    let prefix = options.prefix.as_str();
+    let ascending = options.order == ScanOrder::Ascending;
+    let want = options.limit.map(|limit| limit + 1);
+    let mut items: Vec<(String, bool)> = Vec::new();
+    let mut scan = options.clone();
+
+    // Descending, `< cursor` already excludes the keys under a common prefix
+    if let Some(after) = &options.after
+        && ascending
+        && common_prefix(after, prefix, delimiter).is_some()
+    {
+        scan.after = None;
+        scan.start = prefix_successor(after);
+        if scan.start.is_none() {
+            return Ok(ListPage {
+                keys: Vec::new(),
+                common_prefixes: Vec::new(),
+                next: None,
+            });
+        }
+    }
+
+    'pages: loop {
+        scan.limit = Some(want.map_or(LIST_SCAN_ROWS, |want| {
+            (want - items.len()).min(LIST_SCAN_ROWS)
+        }));
+        let page = scan_page(&mut *conn, ctx, &scan).await?;
+        let mut last_prefix = None;
+        for key in page.keys {
+            match common_prefix(&key, prefix, delimiter) {
+                Some(common) if last_prefix.as_deref() == Some(common) => {
+                    continue;
+                }
+                Some(common) => {
+                    last_prefix = Some(common.to_string());
+                    items.push((common.to_string(), true));
+                }
+                None => {
+                    last_prefix = None;
+                    items.push((key, false));
+                }
+            }
+            if want == Some(items.len()) {
+                break 'pages;
+            }
+        }
+
+        let Some(next) = page.next else { break };
+        match last_prefix {
+            // Jump past the rest of a common prefix the page ended in
+            Some(common) if ascending => match prefix_successor(&common) {
+                Some(start) => {
+                    scan.after = None;
+                    scan.start = Some(start);
+                }
+                None => break,
+            },
+            Some(common) => scan.after = Some(common),
+            None => scan.after = Some(next.0),
+        }
+    }
+
+    let (items, next) = into_page(items, options, |(name, _)| name.as_str());
+    let (common_prefixes, keys): (Vec<_>, Vec<_>) =
+        items.into_iter().partition(|(_, is_prefix)| *is_prefix);
+    Ok(ListPage {
+        keys: keys.into_iter().map(|(key, _)| key).collect(),
+        common_prefixes: common_prefixes
+            .into_iter()
+            .map(|(prefix, _)| prefix)
+            .collect(),
+        next,
+    })
+}
+
+/// Is `query_str` a SELECT, i.e. does it only read and return rows?
+///
+/// Leading comments and parentheses are skipped. A `WITH` query counts when
+/// its main statement is a SELECT and no common table expression writes.
+pub(crate) fn is_select(backend_type: BackendType, query_str: &str) -> bool {
+    const WRITES: [&str; 4] = ["INSERT", "UPDATE", "DELETE", "MERGE"];
+    let tokens = script::tokenize(query_str, backend_type).filter(|token| {
+        !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment)
+    });
+
+    // Depth of the `WITH`, whose main statement follows its CTEs
+    let mut with_depth = None;
+    let mut depth = 0;
+    let mut opens_body = false;
+    for token in tokens {
+        match (token.kind, token.text) {
+            (TokenKind::Other, "(") => {
+                depth += 1;
+                opens_body = true;
+                continue;
+            }
+            (TokenKind::Other, ")") => depth -= 1,
+            (TokenKind::Word, word) => {
+                let word = word.to_ascii_uppercase();
+                let writes = WRITES.contains(&word.as_str());
+                match with_depth {
+                    None if word == "WITH" => with_depth = Some(depth),
+                    None => return word == "SELECT",
+                    // The main statement, or a data-modifying CTE such as
+                    // `(DELETE ... RETURNING *)`
+                    Some(base) if writes && (depth == base || opens_body) => {
+                        return false;
+                    }
+                    Some(base) if depth == base && word == "SELECT" => {
+                        return true;
+                    }
+                    Some(_) => {}
+                }
+            }
+            _ => {}
+        }
+        opens_body = false;
+    }
+    false
+}
+
+/// Run a raw statement, returning rows for SELECT and a row count otherwise
+///
+/// `?` placeholders in `query_str` are bound positionally to `params`.
+pub(crate) async fn query<'e, E>(
+    executor: E,
+    backend_type: BackendType,
+    query_str: &str,
+    params: &[SqlValue],
+) -> agentdb::Result<SqlQueryResult>
+where
+    E: Executor<'e, Database = Any>,
+{
+    let sql = rewrite_placeholders(backend_type, query_str);
+
+    if is_select(backend_type, query_str) {
+        let rows: Vec<AnyRow> = bind_params(sqlx::query(&sql), params)
+            .fetch_all(executor)
+            .await
+            .map_err(backend_err)?;
+
+        convert_rows(rows).map_err(backend_err)
+    } else {
+        let result = bind_params(sqlx::query(&sql), params)
+            .execute(executor)
+            .await
+            .map_err(backend_err)?;
+
+        Ok(SqlQueryResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: result.rows_affected() as usize,
+        })
+    }
+}
+
+/// Bind `params` to `query` in order
+pub(crate) fn bind_params<'q>(
+    mut query: Query<'q, Any, AnyArguments<'q>>,
+    params: &'q [SqlValue],
+) -> Query<'q, Any, AnyArguments<'q>> {
+    for param in params {
+        query = match param {
+            SqlValue::Null => query.bind(Option::<String>::None),
+            SqlValue::Bool(v) => query.bind(*v),
+            SqlValue::Int(v) => query.bind(*v),
+            SqlValue::Float(v) => query.bind(*v),
+            SqlValue::Text(v) => query.bind(v.as_str()),
+            SqlValue::Blob(v) => query.bind(v.as_slice()),
+        };
+    }
+    query
+}
+
+/// Rewrite `?` placeholders into the backend's native bind syntax
+///
+/// SQLite and MySQL accept `?` as-is. For PostgreSQL each `?` outside of
+/// string literals, quoted identifiers, dollar-quoted bodies and comments is
+/// replaced by `$1..$n`, so the same query text works on every backend.
+/// PostgreSQL's jsonb operators clash with this: `?|` and `?&` are kept as
+/// operators, and the plain `?` operator has to be written `??`.
+fn rewrite_placeholders(backend_type: BackendType, sql: &str) -> Cow<'_, str> {
+    match backend_type {
+        #[cfg(feature = "sqlite")]
+        BackendType::Sqlite => Cow::Borrowed(sql),
+        #[cfg(feature = "postgres")]
+        BackendType::Postgres => Cow::Owned(numbered_placeholders(sql)),
+        #[cfg(feature = "mysql")]
+        BackendType::Mysql => Cow::Borrowed(sql),
+    }
+}
+
+/// Replace every bare `?` in `sql` with `$1`, `$2`, ...
+///
+/// `??` becomes a literal `?`. A `?` right before `|` or `&` is the jsonb
+/// `?|` or `?&` operator and is kept, unless that is the start of `||` or
+/// `&&`, which only follow a value.
+#[cfg(feature = "postgres")]
+fn numbered_placeholders(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len() + 8);
+    let mut next = 1;
+    let tokens: Vec<_> =
+        script::tokenize(sql, BackendType::Postgres).collect();
+    let other = |i: usize| {
+        tokens
+            .get(i)
+            .filter(|token| token.kind == TokenKind::Other)
+            .map(|token| token.text)
+    };
+
+    let mut i = 0;
+    while let Some(token) = tokens.get(i) {
+        i += 1;
+        let operator = matches!(
+            other(i),
+            Some(op @ ("|" | "&")) if other(i + 1) != Some(op)
+        );
+        if other(i - 1) != Some("?") {
+            out.push_str(token.text);
+        } else if other(i) == Some("?") {
+            out.push('?');
+            i += 1;
+        } else if operator {
+            out.push('?');
+        } else {
+            out.push_str(&format!("${}", next));
+            next += 1;
+        }
+    }
+
+    out
+}
+
+/// Execute every statement of a multi-statement script in order
+pub(crate) async fn execute_script(
+    conn: &mut AnyConnection,
+    backend_type: BackendType,
+    sql: &str,
+) -> Result<()> {
+    for (idx, statement) in
+        script::split_statements(sql, backend_type).into_iter().enumerate()
+    {
+        sqlx::query(statement).execute(&mut *conn).await.map_err(|e| {
+            SqlError::Query(format!(
+                "Failed to execute statement #{}: {} - Error: {}",
+                idx, statement, e
+            ))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Convert SQLx rows into a typed result, collecting column metadata
+fn convert_rows(
+    rows: Vec<AnyRow>,
+) -> std::result::Result<SqlQueryResult, sqlx::Error> {
+    let Some(first) = rows.first() else {
+        return Ok(SqlQueryResult {
+            columns: Vec::new(),
+            rows: Vec::new(),
+            rows_affected: 0,
+        });
+    };
+
+    let mut columns: Vec<SqlColumn> = first
+        .columns()
+        .iter()
+        .map(|column| SqlColumn {
+            name: column.name().to_string(),
+            sql_type: sql_type(column.type_info()),
+        })
+        .collect();
+    let names: Arc<[String]> =
+        columns.iter().map(|c| c.name.clone()).collect();
+
+    let mut sql_rows = Vec::with_capacity(rows.len());
+    for row in rows {
+        let values = (0..names.len())
+            .map(|i| convert_value(&row, i))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        // Fill in types the driver could not declare from the first non-NULL value
+        for (column, value) in columns.iter_mut().zip(&values) {
+            if column.sql_type == SqlType::Null {
+                column.sql_type = value.sql_type();
+            }
+        }
+
+        sql_rows.push(SqlRow::new(names.clone(), values));
+    }
+
+    Ok(SqlQueryResult { columns, rows: sql_rows, rows_affected: 0 })
+}
+
+/// Decode a single column, keeping its SQL type and NULL-ness
+fn convert_value(
+    row: &AnyRow,
+    i: usize,
+) -> std::result::Result<SqlValue, sqlx::Error> {
+    let raw = row.try_get_raw(i)?;
+    if raw.is_null() {
+        return Ok(SqlValue::Null);
+    }
+
+    let type_info = raw.type_info();
+    Ok(match sql_type(&type_info) {
+        SqlType::Null => SqlValue::Null,
+        SqlType::Bool => SqlValue::Bool(row.try_get(i)?),
+        SqlType::Int => SqlValue::Int(row.try_get(i)?),
+        // `f64` refuses single-precision columns, so widen them here
+        SqlType::Float if type_info.name() == "REAL" => {
+            SqlValue::Float(row.try_get::<f32, _>(i)?.into())
+        }
+        SqlType::Float => SqlValue::Float(row.try_get(i)?),
+        SqlType::Text => SqlValue::Text(row.try_get(i)?),
+        SqlType::Blob => SqlValue::Blob(row.try_get(i)?),
+    })
+}
+
+/// Map the driver-agnostic type name onto [`SqlType`]
+///
+/// `AnyTypeInfoKind` is not re-exported by sqlx, so match on its stable
+/// `TypeInfo::name()` instead.
+fn sql_type(type_info: &AnyTypeInfo) -> SqlType {
+    match type_info.name() {
+        "BOOLEAN" => SqlType::Bool,
+        "SMALLINT" | "INTEGER" | "BIGINT" => SqlType::Int,
+        "REAL" | "DOUBLE" => SqlType::Float,
+        "TEXT" => SqlType::Text,
+        "BLOB" => SqlType::Blob,
+        _ => SqlType::Null,
+    }
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_select() {
+        let pg = BackendType::Postgres;
+        for sql in [
+            "SELECT 1",
+            "  -- note\n/* a */ select 1",
+            "((SELECT 1) UNION (SELECT 2))",
+            "WITH t AS (SELECT 1) SELECT * FROM t",
+            "WITH RECURSIVE t(n) AS (VALUES (1) UNION SELECT n + 1 FROM t) \
+             SELECT n FROM t",
+            "with a as (select 1), \"b\" as materialized (select 2) \
+             select * from a, \"b\"",
+        ] {
+            assert!(is_select(pg, sql), "{}", sql);
+        }
+        for sql in [
+            "INSERT INTO t SELECT 1",
+            "-- SELECT\nDELETE FROM t",
+            "'SELECT'",
+            "WITH t AS (SELECT 1) DELETE FROM u USING t",
+            "WITH d AS (DELETE FROM u RETURNING *) SELECT * FROM d",
+            "WITH t AS (SELECT 1) INSERT INTO u SELECT * FROM t",
+        ] {
+            assert!(!is_select(pg, sql), "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_numbered_placeholders() {
+        assert_eq!(
+            numbered_placeholders("SELECT * FROM t WHERE a = ? AND b = ?"),
+            "SELECT * FROM t WHERE a = $1 AND b = $2"
+        );
+        assert_eq!(
+            numbered_placeholders(
+                "SELECT '?', \"a?\", 'it''s ?' FROM t WHERE x = ?"
+            ),
+            "SELECT '?', \"a?\", 'it''s ?' FROM t WHERE x = $1"
+        );
+        assert_eq!(
+            numbered_placeholders(
+                "SELECT ? -- why?\n/* or? */ , $x$ ? $x$, $$?$$, ?"
+            ),
+            "SELECT $1 -- why?\n/* or? */ , $x$ ? $x$, $$?$$, $2"
+        );
+        // jsonb operators, and `??` for a literal `?`
+        assert_eq!(
+            numbered_placeholders(
+                "SELECT d ?| ?, d ?& ?, d ?? ?, d??'k', ?||'x', ? && ?&&?"
+            ),
+            "SELECT d ?| $1, d ?& $2, d ? $3, d?'k', $4||'x', $5 && $6&&$7"
+        );
+    }
+}
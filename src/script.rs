@@ -0,0 +1,434 @@
+//! SQL script tokenizing and statement splitting
+//!
+//! Migration scripts and [`SqlBackend::execute_script`] input contain many
+//! statements, but SQLx executes one at a time. The splitter here only
+//! breaks on `;` outside of string literals, quoted identifiers, comments,
+//! PostgreSQL dollar-quoted bodies and `BEGIN ... END` blocks of triggers
+//! and stored routines.
+//!
+//! [`SqlBackend::execute_script`]: crate::SqlBackend::execute_script
+
+use crate::backend::BackendType;
+
+/// Lexical class of a [`Token`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// Keyword or unquoted identifier
+    Word,
+    /// String literal, quoted identifier or dollar-quoted body
+    Quoted,
+    /// Line or block comment
+    Comment,
+    Whitespace,
+    /// Any other single character (punctuation, operators, `;`, `?`)
+    Other,
+}
+
+/// A slice of the input with its lexical class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// Byte offset of `text` in the input
+    pub start: usize,
+}
+
+/// Split `sql` into tokens using the quoting rules of `backend_type`
+pub(crate) fn tokenize(
+    sql: &str,
+    backend_type: BackendType,
+) -> impl Iterator<Item = Token<'_>> {
+    Tokenizer { sql, bytes: sql.as_bytes(), pos: 0, backend_type }
+}
+
+struct Tokenizer<'a> {
+    sql: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    backend_type: BackendType,
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let start = self.pos;
+        let c = *self.bytes.get(start)?;
+        let next = self.bytes.get(start + 1).copied();
+
+        // All delimiters are ASCII, so byte offsets always land on UTF-8
+        // character boundaries.
+        let (kind, end) = match c {
+            b'\'' => {
+                (TokenKind::Quoted, self.quoted(start, b'\'', self.is_mysql()))
+            }
+            b'"' => {
+                (TokenKind::Quoted, self.quoted(start, b'"', self.is_mysql()))
+            }
+            b'`' if !self.is_postgres() => {
+                (TokenKind::Quoted, self.quoted(start, b'`', false))
+            }
+            b'[' if self.is_sqlite() => (
+                TokenKind::Quoted,
+                self.find_bytes(start + 1, b"]")
+                    .map_or(self.bytes.len(), |p| p + 1),
+            ),
+            // PostgreSQL escape string: E'...'
+            b'E' | b'e' if self.is_postgres() && next == Some(b'\'') => {
+                (TokenKind::Quoted, self.quoted(start + 1, b'\'', true))
+            }
+            b'-' if next == Some(b'-') => {
+                (TokenKind::Comment, self.line_end(start))
+            }
+            b'#' if self.is_mysql() => {
+                (TokenKind::Comment, self.line_end(start))
+            }
+            b'/' if next == Some(b'*') => {
+                (TokenKind::Comment, self.block_comment_end(start))
+            }
+            b'$' if self.is_postgres() => match self.dollar_tag(start) {
+                Some(tag_end) => {
+                    let tag = &self.bytes[start..tag_end];
+                    let end = self
+                        .find_bytes(tag_end, tag)
+                        .map_or(self.bytes.len(), |p| p + tag.len());
+                    (TokenKind::Quoted, end)
+                }
+                None => (TokenKind::Other, start + 1),
+            },
+            c if c.is_ascii_whitespace() => (
+                TokenKind::Whitespace,
+                self.span(start, |c| c.is_ascii_whitespace()),
+            ),
+            c if is_word_byte(c) => {
+                (TokenKind::Word, self.span(start, is_word_byte))
+            }
+            _ => (TokenKind::Other, self.char_end(start)),
+        };
+
+        self.pos = end;
+        Some(Token { kind, text: &self.sql[start..end], start })
+    }
+}
+
+impl Tokenizer<'_> {
+    fn is_sqlite(&self) -> bool {
+        #[cfg(feature = "sqlite")]
+        if self.backend_type == BackendType::Sqlite {
+            return true;
+        }
+        false
+    }
+
+    fn is_postgres(&self) -> bool {
+        #[cfg(feature = "postgres")]
+        if self.backend_type == BackendType::Postgres {
+            return true;
+        }
+        false
+    }
+
+    fn is_mysql(&self) -> bool {
+        #[cfg(feature = "mysql")]
+        if self.backend_type == BackendType::Mysql {
+            return true;
+        }
+        false
+    }
+
+    /// End of the quoted section opening at `start`
+    ///
+    /// A doubled quote always escapes itself; `backslash` additionally
+    /// enables `\x` escapes (MySQL strings, PostgreSQL `E''` strings).
+    fn quoted(&self, start: usize, quote: u8, backslash: bool) -> usize {
+        let mut i = start + 1;
+        while i < self.bytes.len() {
+            match self.bytes[i] {
+                b'\\' if backslash => i += 2,
+                c if c == quote => {
+                    if self.bytes.get(i + 1) == Some(&quote) {
+                        i += 2;
+                    } else {
+                        return i + 1;
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+        self.bytes.len()
+    }
+
+    fn line_end(&self, start: usize) -> usize {
+        self.find_bytes(start, b"\n").unwrap_or(self.bytes.len())
+    }
+
+    /// End of the block comment at `start`; PostgreSQL comments nest
+    fn block_comment_end(&self, start: usize) -> usize {
+        let nested = self.is_postgres();
+        let mut depth = 0;
+        let mut i = start;
+        while i + 1 < self.bytes.len() {
+            match (self.bytes[i], self.bytes[i + 1]) {
+                (b'/', b'*') if depth == 0 || nested => {
+                    depth += 1;
+                    i += 2;
+                }
+                (b'*', b'/') => {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        return i;
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+        self.bytes.len()
+    }
+
+    /// End of the `$tag$` delimiter at `start`, if there is one
+    fn dollar_tag(&self, start: usize) -> Option<usize> {
+        let mut i = start + 1;
+        while let Some(&c) = self.bytes.get(i) {
+            match c {
+                b'$' => return Some(i + 1),
+                // A tag cannot start with a digit ($1 is a parameter)
+                c if c.is_ascii_digit() && i == start + 1 => return None,
+                c if is_word_byte(c) => i += 1,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    fn find_bytes(&self, from: usize, needle: &[u8]) -> Option<usize> {
+        self.bytes
+            .get(from..)?
+            .windows(needle.len())
+            .position(|w| w == needle)
+            .map(|p| from + p)
+    }
+
+    fn span(&self, start: usize, pred: impl Fn(u8) -> bool) -> usize {
+        self.bytes[start..]
+            .iter()
+            .position(|&c| !pred(c))
+            .map_or(self.bytes.len(), |p| start + p)
+    }
+
+    fn char_end(&self, start: usize) -> usize {
+        start + self.sql[start..].chars().next().map_or(1, char::len_utf8)
+    }
+}
+
+/// Identifier characters; non-ASCII bytes are treated as part of a word
+fn is_word_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c >= 0x80
+}
+
+/// Split a script into individual statements
+///
+/// Statements start at their first keyword and are returned without their
+/// terminating `;`. Chunks that contain only whitespace and comments are
+/// dropped. Inside `CREATE TRIGGER`, `FUNCTION`, `PROCEDURE` and `EVENT`
+/// statements, `;` does not end the statement while a `BEGIN`/`CASE` block
+/// is open.
+pub(crate) fn split_statements(
+    sql: &str,
+    backend_type: BackendType,
+) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut state = StatementState::default();
+    // Byte offset of the first code token of the current statement
+    let mut start = None;
+
+    for token in tokenize(sql, backend_type) {
+        match token.kind {
+            TokenKind::Whitespace | TokenKind::Comment => continue,
+            TokenKind::Word => {}
+            // A non-word token after END closes the innermost block
+            _ => state.close_end(),
+        }
+
+        if token.text == ";" && state.depth == 0 {
+            if let Some(start) = start.take() {
+                statements.push(sql[start..token.start].trim_end());
+            }
+            state = StatementState::default();
+            continue;
+        }
+
+        start.get_or_insert(token.start);
+        state.push(token);
+    }
+
+    if let Some(start) = start {
+        statements.push(sql[start..].trim_end());
+    }
+
+    statements
+}
+
+/// Tracks whether the current statement is inside a `BEGIN ... END` block
+#[derive(Default)]
+struct StatementState {
+    /// Any token besides whitespace and comments seen
+    has_code: bool,
+    /// Statement starts with `CREATE`
+    is_create: bool,
+    /// A `(` was seen; routine keywords after it are column names
+    in_parens: bool,
+    /// Statement creates a trigger or stored routine
+    is_routine: bool,
+    /// Open `BEGIN`/`CASE` blocks
+    depth: usize,
+    /// Previous word was `END`; the next word decides what it closes
+    after_end: bool,
+}
+
+impl StatementState {
+    fn push(&mut self, token: Token<'_>) {
+        let first = !self.has_code;
+        self.has_code = true;
+
+        let word = match token.kind {
+            TokenKind::Word => token.text.to_ascii_uppercase(),
+            _ => {
+                if token.text == "(" {
+                    self.in_parens = true;
+                }
+                return;
+            }
+        };
+
+        if first {
+            self.is_create = word == "CREATE";
+        } else if self.is_create && !self.is_routine && !self.in_parens {
+            self.is_routine = matches!(
+                word.as_str(),
+                "TRIGGER" | "FUNCTION" | "PROCEDURE" | "EVENT"
+            );
+        }
+
+        if !self.is_routine {
+            return;
+        }
+
+        if self.after_end {
+            self.after_end = false;
+            match word.as_str() {
+                // END IF / END LOOP / ... close blocks that were not counted
+                "IF" | "LOOP" | "WHILE" | "REPEAT" => return,
+                // END CASE closes the CASE that was counted
+                "CASE" => {
+                    self.depth = self.depth.saturating_sub(1);
+                    return;
+                }
+                _ => self.depth = self.depth.saturating_sub(1),
+            }
+        }
+
+        match word.as_str() {
+            "BEGIN" | "CASE" => self.depth += 1,
+            "END" => self.after_end = true,
+            _ => {}
+        }
+    }
+
+    /// Resolve a pending `END` that is not followed by a block keyword
+    fn close_end(&mut self) {
+        if self.after_end {
+            self.after_end = false;
+            self.depth = self.depth.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_split_sqlite() {
+        let sql = "
+            -- leading comment; with a semicolon
+            CREATE TABLE t (a TEXT DEFAULT 'x;y', \"b;\" INTEGER); -- trailing; comment
+            /* block; comment */
+            CREATE TRIGGER tr AFTER INSERT ON t BEGIN
+                UPDATE t SET a = CASE WHEN a = 'x' THEN 'y' ELSE a END;
+                DELETE FROM t WHERE a = 'end;';
+            END;
+            INSERT INTO t (a) VALUES ('it''s; fine')
+        ";
+
+        let statements = split_statements(sql, BackendType::Sqlite);
+        assert_eq!(statements.len(), 3, "{:#?}", statements);
+        assert!(statements[0].starts_with("CREATE TABLE t"));
+        assert!(statements[0].ends_with("\"b;\" INTEGER)"));
+        assert!(statements[1].starts_with("CREATE TRIGGER"));
+        assert!(statements[1].ends_with("END"));
+        assert_eq!(statements[2], "INSERT INTO t (a) VALUES ('it''s; fine')");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_split_drops_comment_only_chunks() {
+        let sql = "SELECT 1; -- done\n;; /* nothing */";
+        assert_eq!(
+            split_statements(sql, BackendType::Sqlite),
+            vec!["SELECT 1"]
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_create_table_with_keyword_columns() {
+        let sql =
+            "CREATE TABLE audit (event TEXT, begin_at INTEGER); SELECT 1";
+        assert_eq!(split_statements(sql, BackendType::Sqlite).len(), 2);
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_split_postgres() {
+        let sql = "
+            CREATE FUNCTION touch() RETURNS trigger AS $$
+            BEGIN
+                NEW.updated_at := now(); -- set; timestamp
+                RETURN NEW;
+            END;
+            $$ LANGUAGE plpgsql;
+            CREATE FUNCTION f() RETURNS text AS $body$ SELECT 'a;b' $body$ LANGUAGE sql;
+            SELECT E'it\\'s; escaped', $1 /* outer /* nested; */ still comment; */;
+        ";
+
+        let statements = split_statements(sql, BackendType::Postgres);
+        assert_eq!(statements.len(), 3, "{:#?}", statements);
+        assert!(statements[0].ends_with("LANGUAGE plpgsql"));
+        assert!(statements[1].ends_with("LANGUAGE sql"));
+        assert!(statements[2].starts_with("SELECT E'"));
+    }
+
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn test_split_mysql() {
+        let sql = "
+            # hash comment; here
+            CREATE PROCEDURE p(IN n INT)
+            BEGIN
+                IF n > 0 THEN
+                    SELECT 'a\\';b';
+                END IF;
+                CASE n WHEN 1 THEN SELECT 1; ELSE SELECT 2; END CASE;
+            END;
+            SELECT `odd;name` FROM t
+        ";
+
+        let statements = split_statements(sql, BackendType::Mysql);
+        assert_eq!(statements.len(), 2, "{:#?}", statements);
+        assert!(statements[0].starts_with("CREATE PROCEDURE"));
+        assert!(statements[0].ends_with("END"));
+        assert_eq!(statements[1], "SELECT `odd;name` FROM t");
+    }
+}
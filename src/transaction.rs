@@ -0,0 +1,302 @@
+//! Transaction support for the SQL backend
+//!
+//! [`SqlTransaction`] wraps a SQLx transaction and exposes the same key-value
+//! and query operations as [`SqlBackend`](crate::SqlBackend). Changes become
+//! visible to other connections only after [`commit`](Transaction::commit);
+//! dropping the transaction without committing rolls it back.
+
+use crate::{
+    backend::SqlContext,
+    error::Result,
+    kv::{
+        CasOutcome, EntryMetadata, ListPage, ScanOptions, ScanPage,
+        VersionedValue,
+    },
+    ops,
+    value::{SqlQueryResult, SqlRow, SqlValue},
+};
+use agentdb::{AgentDbError, QueryResult, ScanResult, Transaction, Value};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use sqlx::any::Any;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// An open database transaction
+///
+/// Obtained from [`SqlBackend::transaction`](crate::SqlBackend::transaction)
+/// or, type-erased, from [`AgentDB::begin`](agentdb::AgentDB::begin).
+pub struct SqlTransaction {
+    tx: Mutex<sqlx::Transaction<'static, Any>>,
+    ctx: SqlContext,
+    read_only: bool,
+}
+
+impl SqlTransaction {
+    pub(crate) fn new(
+        tx: sqlx::Transaction<'static, Any>,
+        ctx: SqlContext,
+        read_only: bool,
+    ) -> Self {
+        Self { tx: Mutex::new(tx), ctx, read_only }
+    }
+
+    /// Store a key-value pair inside the transaction
+    pub async fn put(&self, key: &str, value: Value) -> agentdb::Result<()> {
+        ops::ensure_writable(self.read_only, "put")?;
+        let mut tx = self.tx.lock().await;
+        ops::put(&mut **tx, &self.ctx, key, &value, None).await
+    }
+
+    /// Store a key-value pair that expires after `ttl`
+    ///
+    /// See [`SqlBackend::put_with_ttl`](crate::SqlBackend::put_with_ttl).
+    pub async fn put_with_ttl(
+        &self,
+        key: &str,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<()> {
+        ops::ensure_writable(self.read_only, "put_with_ttl")?;
+        let mut tx = self.tx.lock().await;
+        let expires_at = Some(ops::expiry(ttl));
+        Ok(ops::put(&mut **tx, &self.ctx, key, &value, expires_at).await?)
+    }
+
+    /// Make an existing key expire after `ttl`
+    pub async fn expire(&self, key: &str, ttl: Duration) -> Result<()> {
+        ops::ensure_writable(self.read_only, "expire")?;
+        let mut tx = self.tx.lock().await;
+        Ok(ops::expire(&mut **tx, &self.ctx, key, ops::expiry(ttl)).await?)
+    }
+
+    /// Retrieve a value by key, including uncommitted writes
+    pub async fn get(&self, key: &str) -> agentdb::Result<Option<Value>> {
+        let mut tx = self.tx.lock().await;
+        ops::get(&mut **tx, &self.ctx, key).await
+    }
+
+    /// Retrieve a value and its metadata, including uncommitted writes
+    pub async fn get_with_metadata(
+        &self,
+        key: &str,
+    ) -> Result<Option<(Value, EntryMetadata)>> {
+        let mut tx = self.tx.lock().await;
+        ops::get_with_metadata(&mut **tx, &self.ctx, key).await
+    }
+
+    /// Delete a key inside the transaction
+    ///
+    /// An expired key is purged but reported as `NotFound`.
+    pub async fn delete(&self, key: &str) -> agentdb::Result<()> {
+        ops::ensure_writable(self.read_only, "delete")?;
+        let mut tx = self.tx.lock().await;
+        ops::delete(&mut tx, &self.ctx, key).await
+    }
+
+    /// Check if a key exists, including uncommitted writes
+    pub async fn exists(&self, key: &str) -> agentdb::Result<bool> {
+        let mut tx = self.tx.lock().await;
+        ops::exists(&mut **tx, &self.ctx, key).await
+    }
+
+    /// Scan keys with a prefix
+    pub async fn scan(&self, prefix: &str) -> agentdb::Result<ScanResult> {
+        let mut tx = self.tx.lock().await;
+        ops::scan(&mut **tx, &self.ctx, prefix).await
+    }
+
+    /// Fetch a value and its version, including uncommitted writes
+    pub async fn get_versioned(
+        &self,
+        key: &str,
+    ) -> Result<Option<VersionedValue>> {
+        let mut tx = self.tx.lock().await;
+        ops::get_versioned(&mut **tx, &self.ctx, key).await
+    }
+
+    /// Replace the value of `key` only if its version is still `expected`
+    ///
+    /// See [`SqlBackend::put_if_version`](crate::SqlBackend::put_if_version).
+    pub async fn put_if_version(
+        &self,
+        key: &str,
+        value: Value,
+        expected: i64,
+    ) -> Result<CasOutcome> {
+        ops::ensure_writable(self.read_only, "put_if_version")?;
+        let mut tx = self.tx.lock().await;
+        ops::put_if_version(&mut tx, &self.ctx, key, &value, expected).await
+    }
+
+    /// Create `key` only if it does not exist or has expired
+    pub async fn put_if_absent(
+        &self,
+        key: &str,
+        value: Value,
+    ) -> Result<CasOutcome> {
+        ops::ensure_writable(self.read_only, "put_if_absent")?;
+        let mut tx = self.tx.lock().await;
+        ops::put_if_absent(&mut tx, &self.ctx, key, &value).await
+    }
+
+    /// Delete `key` only if its version is still `expected`
+    pub async fn delete_if_version(
+        &self,
+        key: &str,
+        expected: i64,
+    ) -> Result<CasOutcome> {
+        ops::ensure_writable(self.read_only, "delete_if_version")?;
+        let mut tx = self.tx.lock().await;
+        ops::delete_if_version(&mut tx, &self.ctx, key, expected).await
+    }
+
+    /// Add `delta` to the integer stored under `key`
+    ///
+    /// See [`SqlBackend::increment`](crate::SqlBackend::increment).
+    pub async fn increment(&self, key: &str, delta: i64) -> Result<i64> {
+        ops::ensure_writable(self.read_only, "increment")?;
+        let mut tx = self.tx.lock().await;
+        ops::increment(&mut tx, &self.ctx, key, delta).await
+    }
+
+    /// Subtract `delta` from the integer stored under `key`
+    pub async fn decrement(&self, key: &str, delta: i64) -> Result<i64> {
+        self.increment(key, ops::negate(key, delta)?).await
+    }
+
+    /// Store many key-value pairs inside the transaction
+    ///
+    /// See [`SqlBackend::put_many`](crate::SqlBackend::put_many).
+    pub async fn put_many(&self, entries: &[(String, Value)]) -> Result<()> {
+        ops::ensure_writable(self.read_only, "put_many")?;
+        let mut tx = self.tx.lock().await;
+        ops::put_many(&mut tx, &self.ctx, entries).await
+    }
+
+    /// Fetch many keys, including uncommitted writes
+    pub async fn get_many<K: AsRef<str>>(
+        &self,
+        keys: &[K],
+    ) -> Result<Vec<Option<Value>>> {
+        let mut tx = self.tx.lock().await;
+        ops::get_many(&mut tx, &self.ctx, keys).await
+    }
+
+    /// Delete many keys inside the transaction, returning how many existed
+    pub async fn delete_many<K: AsRef<str>>(&self, keys: &[K]) -> Result<u64> {
+        ops::ensure_writable(self.read_only, "delete_many")?;
+        let mut tx = self.tx.lock().await;
+        ops::delete_many(&mut tx, &self.ctx, keys).await
+    }
+
+    /// Read one page of keys, including uncommitted writes
+    ///
+    /// See [`SqlBackend::scan_page`](crate::SqlBackend::scan_page).
+    pub async fn scan_page(&self, options: &ScanOptions) -> Result<ScanPage> {
+        let mut tx = self.tx.lock().await;
+        ops::scan_page(&mut **tx, &self.ctx, options).await
+    }
+
+    /// Read one page of a delimited listing, including uncommitted writes
+    ///
+    /// See [`SqlBackend::list_page`](crate::SqlBackend::list_page).
+    pub async fn list_page(
+        &self,
+        options: &ScanOptions,
+        delimiter: &str,
+    ) -> Result<ListPage> {
+        let mut tx = self.tx.lock().await;
+        ops::list_page(&mut tx, &self.ctx, options, delimiter).await
+    }
+
+    /// Execute a raw SQL statement inside the transaction
+    ///
+    /// Params bind as text; see [`SqlValue`]'s `From<Value>` for PostgreSQL.
+    pub async fn query(
+        &self,
+        query_str: &str,
+        params: Vec<Value>,
+    ) -> agentdb::Result<QueryResult> {
+        ops::ensure_query_allowed(
+            self.read_only,
+            self.ctx.backend_type,
+            query_str,
+        )?;
+        let params: Vec<SqlValue> =
+            params.into_iter().map(SqlValue::from).collect();
+        let mut tx = self.tx.lock().await;
+        let result =
+            ops::query(&mut **tx, self.ctx.backend_type, query_str, &params)
+                .await?;
+        Ok(result.into_agent_result())
+    }
+
+    /// Execute a query inside the transaction, keeping value types
+    pub async fn query_typed(
+        &self,
+        query_str: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<SqlQueryResult> {
+        ops::ensure_query_allowed(
+            self.read_only,
+            self.ctx.backend_type,
+            query_str,
+        )?;
+        let mut tx = self.tx.lock().await;
+        Ok(ops::query(&mut **tx, self.ctx.backend_type, query_str, &params)
+            .await?)
+    }
+
+    /// Execute a SELECT inside the transaction, deserializing rows into `T`
+    pub async fn query_as<T: DeserializeOwned>(
+        &self,
+        query_str: &str,
+        params: Vec<SqlValue>,
+    ) -> Result<Vec<T>> {
+        self.query_typed(query_str, params)
+            .await?
+            .rows
+            .into_iter()
+            .map(SqlRow::decode)
+            .collect()
+    }
+
+    /// Execute a multi-statement SQL script inside the transaction
+    ///
+    /// See [`SqlBackend::execute_script`](crate::SqlBackend::execute_script).
+    pub async fn execute_script(&self, sql: &str) -> Result<()> {
+        ops::ensure_writable(self.read_only, "execute_script")?;
+        let mut tx = self.tx.lock().await;
+        ops::execute_script(&mut tx, self.ctx.backend_type, sql).await
+    }
+
+    /// Commit the transaction
+    pub async fn commit(self) -> agentdb::Result<()> {
+        self.tx
+            .into_inner()
+            .commit()
+            .await
+            .map_err(|e| AgentDbError::Transaction(e.to_string()))
+    }
+
+    /// Roll back the transaction
+    pub async fn rollback(self) -> agentdb::Result<()> {
+        self.tx
+            .into_inner()
+            .rollback()
+            .await
+            .map_err(|e| AgentDbError::Transaction(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Transaction for SqlTransaction {
+    async fn commit(self: Box<Self>) -> agentdb::Result<()> {
+        SqlTransaction::commit(*self).await
+    }
+
+    async fn rollback(self: Box<Self>) -> agentdb::Result<()> {
+        SqlTransaction::rollback(*self).await
+    }
+}
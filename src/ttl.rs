@@ -0,0 +1,70 @@
+//! Removal of expired key-value entries
+//!
+//! Expired entries are invisible to reads as soon as their `expires_at`
+//! passes, but stay in `kv_store` until deleted. [`purge`] deletes them in
+//! batches, releasing the connection between batches so a large purge does
+//! not hold up other work; [`Sweeper`] runs it periodically.
+
+use crate::{backend::SqlContext, error::Result, health::CountedPool, ops};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Batch size used when none is configured
+pub(crate) const DEFAULT_BATCH_SIZE: u32 = 1000;
+
+/// Delete every expired entry, `batch_size` rows per statement
+pub(crate) async fn purge(
+    pool: &CountedPool,
+    ctx: &SqlContext,
+    batch_size: u32,
+) -> Result<u64> {
+    let mut total = 0;
+    loop {
+        let mut conn = pool.acquire().await?;
+        let removed = ops::purge_expired(&mut *conn, ctx, batch_size).await?;
+        total += removed;
+        if removed < u64::from(batch_size) {
+            return Ok(total);
+        }
+    }
+}
+
+/// Background task purging expired entries every `interval`
+///
+/// The task is aborted when the sweeper is stopped or dropped.
+pub(crate) struct Sweeper {
+    handle: JoinHandle<()>,
+}
+
+impl Sweeper {
+    pub fn spawn(
+        pool: CountedPool,
+        ctx: SqlContext,
+        interval: Duration,
+        batch_size: u32,
+    ) -> Self {
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(
+                tokio::time::MissedTickBehavior::Delay,
+            );
+            loop {
+                ticker.tick().await;
+                // A failed pass (busy database, dropped connection) is
+                // retried on the next tick
+                let _ = purge(&pool, &ctx, batch_size).await;
+            }
+        });
+        Self { handle }
+    }
+
+    pub fn stop(&self) {
+        self.handle.abort();
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,229 @@
+//! Typed query results
+//!
+//! [`agentdb::QueryResult`] stores every column as raw bytes, which loses the
+//! difference between `0`, `"0"`, `''` and `NULL`. The types in this module
+//! keep the SQL type of every value and make `NULL` explicit.
+
+use agentdb::Value;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+/// A single SQL value with its type preserved
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Type of a [`SqlValue`] or result column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlType {
+    Null,
+    Bool,
+    Int,
+    Float,
+    Text,
+    Blob,
+}
+
+impl SqlValue {
+    /// Type of this value
+    pub fn sql_type(&self) -> SqlType {
+        match self {
+            SqlValue::Null => SqlType::Null,
+            SqlValue::Bool(_) => SqlType::Bool,
+            SqlValue::Int(_) => SqlType::Int,
+            SqlValue::Float(_) => SqlType::Float,
+            SqlValue::Text(_) => SqlType::Text,
+            SqlValue::Blob(_) => SqlType::Blob,
+        }
+    }
+
+    /// Is this SQL `NULL`?
+    pub fn is_null(&self) -> bool {
+        matches!(self, SqlValue::Null)
+    }
+
+    /// Encode as raw bytes, as [`agentdb::Row`] stores them
+    ///
+    /// Numbers become their decimal representation, booleans `"1"`/`"0"`
+    /// and `NULL` an empty byte vector.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            SqlValue::Null => Vec::new(),
+            SqlValue::Bool(v) => {
+                if v {
+                    b"1".to_vec()
+                } else {
+                    b"0".to_vec()
+                }
+            }
+            SqlValue::Int(v) => v.to_string().into_bytes(),
+            SqlValue::Float(v) => v.to_string().into_bytes(),
+            SqlValue::Text(v) => v.into_bytes(),
+            SqlValue::Blob(v) => v,
+        }
+    }
+
+    fn into_json(self) -> serde_json::Value {
+        match self {
+            SqlValue::Null => serde_json::Value::Null,
+            SqlValue::Bool(v) => v.into(),
+            SqlValue::Int(v) => v.into(),
+            SqlValue::Float(v) => v.into(),
+            SqlValue::Text(v) => v.into(),
+            SqlValue::Blob(v) => v.into(),
+        }
+    }
+}
+
+/// Raw bytes are treated as text when they are valid UTF-8, blob otherwise
+///
+/// This is how `AgentDB::query` binds its params, so on PostgreSQL, which
+/// does not convert text implicitly, a param compared with or stored in a
+/// non-text column needs a cast such as `?::bigint`. `query_typed` with
+/// [`SqlValue::Int`] and friends binds the native type instead.
+impl From<Value> for SqlValue {
+    fn from(value: Value) -> Self {
+        match String::from_utf8(value.into_bytes()) {
+            Ok(text) => SqlValue::Text(text),
+            Err(e) => SqlValue::Blob(e.into_bytes()),
+        }
+    }
+}
+
+impl From<bool> for SqlValue {
+    fn from(v: bool) -> Self {
+        SqlValue::Bool(v)
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(v: i64) -> Self {
+        SqlValue::Int(v)
+    }
+}
+
+impl From<i32> for SqlValue {
+    fn from(v: i32) -> Self {
+        SqlValue::Int(v.into())
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(v: f64) -> Self {
+        SqlValue::Float(v)
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(v: String) -> Self {
+        SqlValue::Text(v)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(v: &str) -> Self {
+        SqlValue::Text(v.to_string())
+    }
+}
+
+impl From<Vec<u8>> for SqlValue {
+    fn from(v: Vec<u8>) -> Self {
+        SqlValue::Blob(v)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(SqlValue::Null, Into::into)
+    }
+}
+
+/// Name and type of a result column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlColumn {
+    pub name: String,
+    /// Declared column type, or the type of the first non-NULL value when
+    /// the driver does not report one (e.g. SQLite expressions)
+    pub sql_type: SqlType,
+}
+
+/// A single row of a [`SqlQueryResult`]
+#[derive(Debug, Clone)]
+pub struct SqlRow {
+    columns: Arc<[String]>,
+    values: Vec<SqlValue>,
+}
+
+impl SqlRow {
+    pub(crate) fn new(columns: Arc<[String]>, values: Vec<SqlValue>) -> Self {
+        Self { columns, values }
+    }
+
+    /// Get a column value by name
+    pub fn get(&self, name: &str) -> Option<&SqlValue> {
+        let idx = self.columns.iter().position(|c| c == name)?;
+        self.values.get(idx)
+    }
+
+    /// Get a column value by position
+    pub fn get_index(&self, idx: usize) -> Option<&SqlValue> {
+        self.values.get(idx)
+    }
+
+    /// Column values in select order
+    pub fn values(&self) -> &[SqlValue] {
+        &self.values
+    }
+
+    /// Convert into an [`agentdb::Row`], encoding every value as bytes
+    pub fn into_agent_row(self) -> agentdb::Row {
+        self.columns.iter().zip(self.values).fold(
+            agentdb::Row::new(),
+            |row, (name, value)| {
+                row.with_column(name.clone(), Value::new(value.into_bytes()))
+            },
+        )
+    }
+
+    /// Deserialize the row into `T`, matching fields by column name
+    ///
+    /// Blobs deserialize as byte sequences, so they map onto `Vec<u8>`.
+    pub fn decode<T: DeserializeOwned>(self) -> crate::Result<T> {
+        let map: serde_json::Map<String, serde_json::Value> = self
+            .columns
+            .iter()
+            .cloned()
+            .zip(self.values.into_iter().map(SqlValue::into_json))
+            .collect();
+        Ok(serde_json::from_value(serde_json::Value::Object(map))?)
+    }
+}
+
+/// Result of a typed query
+#[derive(Debug, Clone)]
+pub struct SqlQueryResult {
+    /// Result column metadata (empty for statements that return no rows)
+    pub columns: Vec<SqlColumn>,
+
+    /// Rows returned by the query
+    pub rows: Vec<SqlRow>,
+
+    /// Number of rows affected (for INSERT/UPDATE/DELETE)
+    pub rows_affected: usize,
+}
+
+impl SqlQueryResult {
+    /// Convert into an [`agentdb::QueryResult`], encoding every value as bytes
+    pub fn into_agent_result(self) -> agentdb::QueryResult {
+        agentdb::QueryResult::new(
+            self.rows.into_iter().map(SqlRow::into_agent_row).collect(),
+            self.rows_affected,
+        )
+    }
+}
@@ -1,2 +1,1835 @@
 extern crate agentsql;
 use agentsql::*;
+
+use agentdb::AgentDB;
+
+#[tokio::test]
+async fn test_transaction_commit_and_rollback() {
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    // Writes inside a committed transaction become visible
+    let tx = db.transaction().await.unwrap();
+    tx.put("a", b"1".to_vec().into()).await.unwrap();
+    tx.put("b", b"2".to_vec().into()).await.unwrap();
+    assert_eq!(tx.get("a").await.unwrap().unwrap().as_bytes(), b"1");
+    assert_eq!(tx.scan("").await.unwrap().keys, vec!["a", "b"]);
+    tx.delete("b").await.unwrap();
+    assert!(!tx.exists("b").await.unwrap());
+    tx.commit().await.unwrap();
+    assert_eq!(db.get("a").await.unwrap().unwrap().as_bytes(), b"1");
+    assert!(!db.exists("b").await.unwrap());
+
+    // Writes inside a rolled back transaction are discarded
+    let tx = db.transaction().await.unwrap();
+    tx.put("c", b"3".to_vec().into()).await.unwrap();
+    tx.query("DELETE FROM kv_store WHERE key = 'a'", vec![]).await.unwrap();
+    tx.rollback().await.unwrap();
+    assert!(!db.exists("c").await.unwrap());
+    assert!(db.exists("a").await.unwrap());
+
+    // The type-erased AgentDB::begin handle commits as well
+    let tx = db.begin().await.unwrap();
+    tx.commit().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_query_binds_params() {
+    for db in test_backends("query_params").await {
+        let table = db.table_name("tool_calls");
+        db.query(&format!("DELETE FROM {}", table), vec![]).await.unwrap();
+
+        // `Value` params bind as text, which PostgreSQL will not compare
+        // with or store in a BIGINT column without a cast
+        let int = match db.backend_type() {
+            #[cfg(feature = "postgres")]
+            BackendType::Postgres => "?::bigint",
+            _ => "?",
+        };
+        let result = db
+            .query(
+                &format!(
+                    "INSERT INTO {} (name, parameters, started_at) VALUES (?, ?, {})",
+                    table, int
+                ),
+                vec![
+                    b"search".to_vec().into(),
+                    b"x'); DROP TABLE kv_store; --".to_vec().into(),
+                    b"7".to_vec().into(),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.rows_affected, 1);
+
+        let result = db
+            .query(
+                &format!(
+                    "SELECT name, parameters FROM {} WHERE started_at = {}",
+                    table, int
+                ),
+                vec![b"7".to_vec().into()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(
+            result.rows[0].get("parameters").unwrap().as_bytes(),
+            b"x'); DROP TABLE kv_store; --"
+        );
+
+        // Typed params need no cast
+        let result = db
+            .query_typed(
+                &format!("SELECT name FROM {} WHERE started_at = ?", table),
+                vec![SqlValue::Int(7)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            result.rows[0].get("name"),
+            Some(&SqlValue::Text("search".into()))
+        );
+        assert!(db.scan("").await.is_ok());
+    }
+}
+
+#[tokio::test]
+async fn test_query_typed_preserves_types() {
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    let result = db
+        .query_typed("SELECT 0 AS i, '0' AS t, '' AS e, NULL AS n, 1.5 AS f, X'00FF' AS b", vec![])
+        .await
+        .unwrap();
+    let row = &result.rows[0];
+    assert_eq!(row.get("i"), Some(&SqlValue::Int(0)));
+    assert_eq!(row.get("t"), Some(&SqlValue::Text("0".into())));
+    assert_eq!(row.get("e"), Some(&SqlValue::Text(String::new())));
+    assert_eq!(row.get("n"), Some(&SqlValue::Null));
+    assert_eq!(row.get("f"), Some(&SqlValue::Float(1.5)));
+    assert_eq!(row.get("b"), Some(&SqlValue::Blob(vec![0x00, 0xff])));
+    assert_eq!(result.columns[0].sql_type, SqlType::Int);
+    assert_eq!(result.columns[3].sql_type, SqlType::Null);
+}
+
+#[tokio::test]
+async fn test_query_typed_widens_single_precision() {
+    for db in test_backends("single_precision").await {
+        let column = match db.backend_type() {
+            #[cfg(feature = "mysql")]
+            BackendType::Mysql => "FLOAT",
+            _ => "REAL",
+        };
+        let table = db.table_name("floats");
+        db.query_typed(&format!("DROP TABLE IF EXISTS {}", table), vec![])
+            .await
+            .unwrap();
+        db.query_typed(
+            &format!("CREATE TABLE {} (id INTEGER, f {})", table, column),
+            vec![],
+        )
+        .await
+        .unwrap();
+        db.query_typed(
+            &format!("INSERT INTO {} (id, f) VALUES (1, 1.5), (2, ?)", table),
+            vec![SqlValue::Float(-0.25)],
+        )
+        .await
+        .unwrap();
+
+        let result = db
+            .query_typed(
+                &format!("SELECT f FROM {} ORDER BY id", table),
+                vec![],
+            )
+            .await
+            .unwrap();
+        let values: Vec<_> =
+            result.rows.iter().map(|row| row.get("f")).collect();
+        assert_eq!(
+            values,
+            vec![Some(&SqlValue::Float(1.5)), Some(&SqlValue::Float(-0.25))]
+        );
+        assert_eq!(result.columns[0].sql_type, SqlType::Float);
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[tokio::test]
+async fn test_query_keeps_jsonb_operators() {
+    for db in test_backends("jsonb").await {
+        if !matches!(db.backend_type(), BackendType::Postgres) {
+            continue;
+        }
+        // The Any driver cannot decode PostgreSQL booleans
+        let result = db
+            .query_typed(
+                "SELECT (d ?| ARRAY['a', ?])::TEXT AS some, \
+                 (d ?& ARRAY[?])::TEXT AS every, \
+                 (d ?? ?)::TEXT AS has, ? || 'x' AS cat \
+                 FROM (SELECT '{\"a\": 1, \"b\": 2}'::jsonb AS d) t",
+                vec![
+                    SqlValue::Text("z".into()),
+                    SqlValue::Text("c".into()),
+                    SqlValue::Text("b".into()),
+                    SqlValue::Text("w".into()),
+                ],
+            )
+            .await
+            .unwrap();
+        let row = &result.rows[0];
+        let text = |s: &str| Some(SqlValue::Text(s.into()));
+        assert_eq!(row.get("some").cloned(), text("true"));
+        assert_eq!(row.get("every").cloned(), text("false"));
+        assert_eq!(row.get("has").cloned(), text("true"));
+        assert_eq!(row.get("cat").cloned(), text("wx"));
+    }
+}
+
+#[tokio::test]
+async fn test_query_as_decodes_rows() {
+    #[derive(serde::Deserialize)]
+    struct ToolCall {
+        name: String,
+        duration_ms: Option<i64>,
+    }
+
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+    db.query_typed(
+        "INSERT INTO tool_calls (name, started_at, duration_ms) VALUES (?, 0, ?), (?, 0, ?)",
+        vec!["search".into(), 12i64.into(), "fetch".into(), SqlValue::Null],
+    )
+    .await
+    .unwrap();
+
+    let calls: Vec<ToolCall> = db
+        .query_as(
+            "SELECT name, duration_ms FROM tool_calls ORDER BY id",
+            vec![],
+        )
+        .await
+        .unwrap();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(
+        (calls[0].name.as_str(), calls[0].duration_ms),
+        ("search", Some(12))
+    );
+    assert_eq!(
+        (calls[1].name.as_str(), calls[1].duration_ms),
+        ("fetch", None)
+    );
+}
+
+/// Path to a fresh SQLite database file in the system temp directory
+fn temp_db_path() -> String {
+    std::env::temp_dir()
+        .join(format!("agentsql-{}.db", uuid::Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn remove_db(path: &str) {
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+}
+
+/// Backends for tests that must behave the same on every database
+///
+/// Always an in-memory SQLite database, plus the servers named by
+/// `AGENTSQL_TEST_POSTGRES_URL` and `AGENTSQL_TEST_MYSQL_URL` when set.
+/// Each test gets its own table prefix, emptied before use.
+async fn test_backends(test: &str) -> Vec<SqlBackend> {
+    test_backends_with(test, SqlBackendOptions::new()).await
+}
+
+/// [`test_backends`] with extra options
+async fn test_backends_with(
+    test: &str,
+    options: SqlBackendOptions,
+) -> Vec<SqlBackend> {
+    let mut configs = vec![SqlBackendConfig::Sqlite(":memory:".into())];
+    for var in ["AGENTSQL_TEST_POSTGRES_URL", "AGENTSQL_TEST_MYSQL_URL"] {
+        if let Ok(url) = std::env::var(var) {
+            configs.push(SqlBackendConfig::from_url(&url).unwrap());
+        }
+    }
+
+    let mut backends = Vec::new();
+    for config in configs {
+        let options = options.clone().table_prefix(format!("{}_", test));
+        let db = SqlBackend::with_options(config, options).await.unwrap();
+        let keys = db.scan("").await.unwrap().keys;
+        db.delete_many(&keys).await.unwrap();
+        backends.push(db);
+    }
+    backends
+}
+
+#[tokio::test]
+async fn test_concurrent_sqlite_opens_migrate_once() {
+    for _ in 0..10 {
+        let path = temp_db_path();
+        let mut opens = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            let path = path.clone();
+            opens.spawn(async move { SqlBackend::sqlite(&path).await });
+        }
+        while let Some(db) = opens.join_next().await {
+            let db = db.unwrap().unwrap();
+            assert!(db.migration_status().await.unwrap().is_up_to_date());
+            db.close().await.unwrap();
+        }
+        remove_db(&path);
+    }
+}
+
+#[tokio::test]
+async fn test_server_migrations_release_lock() {
+    use std::time::Duration;
+
+    for var in ["AGENTSQL_TEST_POSTGRES_URL", "AGENTSQL_TEST_MYSQL_URL"] {
+        let Ok(url) = std::env::var(var) else { continue };
+        let config = SqlBackendConfig::from_url(&url).unwrap();
+        let options =
+            SqlBackendOptions::new().table_prefix("server_migrations_");
+
+        let first = SqlBackend::with_options(config.clone(), options.clone())
+            .await
+            .unwrap();
+
+        // A lock still held by the first pool would block this one
+        let second = tokio::time::timeout(
+            Duration::from_secs(30),
+            SqlBackend::with_options(config, options),
+        )
+        .await
+        .expect("migration lock was not released")
+        .unwrap();
+        assert!(second.migration_status().await.unwrap().is_up_to_date());
+        first.close().await.unwrap();
+        second.close().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_kv_binary_round_trip() {
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    let bytes: Vec<u8> = (0..=255).collect();
+    db.put("all_bytes", bytes.clone().into()).await.unwrap();
+    db.put("invalid_utf8", vec![0xc3, 0x28, 0x00, 0xff].into()).await.unwrap();
+    db.put("empty", Vec::new().into()).await.unwrap();
+
+    assert_eq!(
+        db.get("all_bytes").await.unwrap().unwrap().as_bytes(),
+        &bytes[..]
+    );
+    assert_eq!(
+        db.get("invalid_utf8").await.unwrap().unwrap().as_bytes(),
+        &[0xc3, 0x28, 0x00, 0xff]
+    );
+    assert_eq!(db.get("empty").await.unwrap().unwrap().as_bytes(), b"");
+}
+
+#[tokio::test]
+async fn test_kv_text_column_is_converted() {
+    let path = temp_db_path();
+
+    // Recreate a database created before the migration ledger, with the
+    // original TEXT column and a row stored as TEXT
+    let db = SqlBackend::sqlite(&path).await.unwrap();
+    db.query("DROP TABLE schema_migrations", vec![]).await.unwrap();
+    db.query("DROP TABLE kv_store", vec![]).await.unwrap();
+    db.query(
+        "CREATE TABLE kv_store (key TEXT PRIMARY KEY, value TEXT NOT NULL, created_at INTEGER, updated_at INTEGER)",
+        vec![],
+    )
+    .await
+    .unwrap();
+    db.query(
+        "INSERT INTO kv_store (key, value) VALUES ('legacy', 'héllo')",
+        vec![],
+    )
+    .await
+    .unwrap();
+    db.close().await.unwrap();
+
+    let db = SqlBackend::sqlite(&path).await.unwrap();
+    assert_eq!(
+        db.get("legacy").await.unwrap().unwrap().as_bytes(),
+        "héllo".as_bytes()
+    );
+    let result = db
+        .query_typed(
+            "SELECT typeof(value) AS t FROM kv_store WHERE key = 'legacy'",
+            vec![],
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.rows[0].get("t"), Some(&SqlValue::Text("blob".into())));
+    db.close().await.unwrap();
+
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_migration_status() {
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    let status = db.migration_status().await.unwrap();
+    assert!(status.is_up_to_date());
+    assert_eq!(status.current_version, Some(status.latest_version));
+    assert!(status.pending.is_empty());
+}
+
+#[tokio::test]
+async fn test_edited_migration_is_rejected() {
+    let path = temp_db_path();
+
+    let db = SqlBackend::sqlite(&path).await.unwrap();
+    db.query(
+        "UPDATE schema_migrations SET checksum = 'edited' WHERE version = 1",
+        vec![],
+    )
+    .await
+    .unwrap();
+    db.close().await.unwrap();
+
+    match SqlBackend::sqlite(&path).await {
+        Err(SqlError::Migration(msg)) => assert!(msg.contains("modified")),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("edited migration was accepted"),
+    }
+
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_execute_script_with_trigger() {
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    db.execute_script(
+        "
+        -- audit table; created by a script
+        CREATE TABLE script_audit (key TEXT, note TEXT DEFAULT 'a;b');
+        CREATE TRIGGER script_audit_kv AFTER INSERT ON kv_store
+        WHEN NEW.key LIKE 'audited:%'
+        BEGIN
+            INSERT INTO script_audit (key) VALUES (NEW.key);
+            INSERT INTO script_audit (key, note) VALUES (NEW.key, 'it''s; second');
+        END; /* trailing comment */
+        ",
+    )
+    .await
+    .unwrap();
+
+    db.put("audited:1", b"v".to_vec().into()).await.unwrap();
+    let result = db
+        .query_typed(
+            "SELECT key, note FROM script_audit ORDER BY note",
+            vec![],
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.rows.len(), 2);
+    assert_eq!(
+        result.rows[0].get("note"),
+        Some(&SqlValue::Text("a;b".into()))
+    );
+    assert_eq!(
+        result.rows[1].get("note"),
+        Some(&SqlValue::Text("it's; second".into()))
+    );
+}
+
+fn notes_migrations() -> MigrationSet {
+    MigrationSet::new("notes").migration(
+        Migration::new(1, "create_notes")
+            .sqlite("CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)")
+            .postgres(
+                "CREATE TABLE notes (id BIGSERIAL PRIMARY KEY, body TEXT)",
+            )
+            .mysql("CREATE TABLE notes (id BIGINT PRIMARY KEY, body TEXT)"),
+    )
+}
+
+#[tokio::test]
+async fn test_registered_migration_set() {
+    let path = temp_db_path();
+    let config = SqlBackendConfig::Sqlite(path.clone());
+
+    let db = SqlBackend::with_options(
+        config.clone(),
+        SqlBackendOptions::new().migration_set(notes_migrations()),
+    )
+    .await
+    .unwrap();
+    let status = db.migration_status_of("notes").await.unwrap();
+    assert_eq!(status.current_version, Some(1));
+    assert!(status.is_up_to_date());
+    db.close().await.unwrap();
+
+    // A later build adds a step; only that step runs on reconnect
+    let notes = notes_migrations().migration(
+        Migration::new(2, "notes_title")
+            .sqlite("ALTER TABLE notes ADD COLUMN title TEXT")
+            .postgres("ALTER TABLE notes ADD COLUMN title TEXT")
+            .mysql("ALTER TABLE notes ADD COLUMN title TEXT"),
+    );
+    let db = SqlBackend::with_options(
+        config,
+        SqlBackendOptions::new().migration_set(notes),
+    )
+    .await
+    .unwrap();
+    db.query(
+        "INSERT INTO notes (id, body, title) VALUES (?, ?, ?)",
+        vec![
+            b"1".to_vec().into(),
+            b"hi".to_vec().into(),
+            b"t".to_vec().into(),
+        ],
+    )
+    .await
+    .unwrap();
+
+    let status = db.migration_status_of("notes").await.unwrap();
+    assert_eq!(status.current_version, Some(2));
+    assert_eq!(status.latest_version, 2);
+    assert!(db.migration_status().await.unwrap().is_up_to_date());
+    assert!(matches!(
+        db.migration_status_of("missing").await,
+        Err(SqlError::Migration(_))
+    ));
+
+    db.close().await.unwrap();
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_invalid_migration_set_is_rejected() {
+    let out_of_order = MigrationSet::new("broken")
+        .migration(Migration::new(2, "second").sqlite("SELECT 1"))
+        .migration(Migration::new(1, "first").sqlite("SELECT 1"));
+    let result = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(":memory:".into()),
+        SqlBackendOptions::new().migration_set(out_of_order),
+    )
+    .await;
+    assert!(matches!(result, Err(SqlError::Migration(_))));
+
+    let shadows_builtin = MigrationSet::new("agentsql");
+    let result = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(":memory:".into()),
+        SqlBackendOptions::new().migration_set(shadows_builtin),
+    )
+    .await;
+    assert!(matches!(result, Err(SqlError::Migration(_))));
+}
+
+#[tokio::test]
+async fn test_migration_modes() {
+    let path = temp_db_path();
+    let config = SqlBackendConfig::Sqlite(path.clone());
+    let connect = |mode| {
+        SqlBackend::with_options(
+            config.clone(),
+            SqlBackendOptions::new().migration_mode(mode),
+        )
+    };
+
+    // Skip leaves an empty database alone
+    let db = connect(MigrationMode::Skip).await.unwrap();
+    assert_eq!(db.migration_status().await.unwrap().current_version, None);
+    db.close().await.unwrap();
+
+    match connect(MigrationMode::Verify).await {
+        Err(SqlError::Migration(msg)) => assert!(msg.contains("behind")),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("empty schema passed verification"),
+    }
+
+    // A deploy job applies the schema step by step
+    let db = connect(MigrationMode::Skip).await.unwrap();
+    db.migrate_to(1).await.unwrap();
+    let status = db.migration_status().await.unwrap();
+    assert_eq!(status.current_version, Some(1));
+    assert_eq!(status.pending, vec![2, 3, 4, 5, 6]);
+    assert!(matches!(db.migrate_to(99).await, Err(SqlError::Migration(_))));
+    db.close().await.unwrap();
+
+    assert!(connect(MigrationMode::Verify).await.is_err());
+
+    let db = connect(MigrationMode::Skip).await.unwrap();
+    db.migrate().await.unwrap();
+    assert!(matches!(db.migrate_to(1).await, Err(SqlError::Migration(_))));
+    db.close().await.unwrap();
+
+    let db = connect(MigrationMode::Verify).await.unwrap();
+    db.put("verified", b"yes".to_vec().into()).await.unwrap();
+    db.close().await.unwrap();
+
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_connect_url_and_env() {
+    let path = temp_db_path();
+    let url = format!("sqlite://{}", path);
+
+    let db = SqlBackend::connect(&url).await.unwrap();
+    db.put("from_url", b"1".to_vec().into()).await.unwrap();
+    db.close().await.unwrap();
+
+    // SAFETY: no other test reads or writes this variable
+    unsafe { std::env::set_var(DATABASE_URL_ENV, &url) };
+    let db = SqlBackend::from_env().await.unwrap();
+    assert!(db.exists("from_url").await.unwrap());
+    db.close().await.unwrap();
+    unsafe { std::env::remove_var(DATABASE_URL_ENV) };
+
+    assert!(matches!(
+        SqlBackend::from_env().await,
+        Err(SqlError::Connection(_))
+    ));
+
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_pool_options() {
+    use std::time::Duration;
+
+    let path = temp_db_path();
+    let options = SqlBackendOptions::new()
+        .max_connections(4)
+        .min_connections(1)
+        .acquire_timeout(Duration::from_secs(5))
+        .idle_timeout(None)
+        .max_lifetime(Duration::from_secs(600))
+        .test_before_acquire(false)
+        .statement_cache_size(16);
+    let db = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(path.clone()),
+        options,
+    )
+    .await
+    .unwrap();
+
+    let db = std::sync::Arc::new(db);
+    let mut writers = tokio::task::JoinSet::new();
+    for i in 0..8u8 {
+        let db = db.clone();
+        writers.spawn(async move {
+            db.put(&format!("pool:{}", i), vec![i].into()).await
+        });
+    }
+    while let Some(result) = writers.join_next().await {
+        result.unwrap().unwrap();
+    }
+    assert_eq!(db.scan("pool:").await.unwrap().keys.len(), 8);
+
+    db.close().await.unwrap();
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_sqlite_pragmas_on_every_connection() {
+    use std::time::Duration;
+
+    let path = temp_db_path();
+    let pragmas = SqlitePragmas::new()
+        .synchronous(Synchronous::Normal)
+        .busy_timeout(Duration::from_millis(1234))
+        .cache_size(-4000)
+        .mmap_size(0);
+    let db = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(path.clone()),
+        SqlBackendOptions::new().max_connections(3).sqlite_pragmas(pragmas),
+    )
+    .await
+    .unwrap();
+
+    // Open transactions pin distinct pool connections
+    let mut transactions = Vec::new();
+    for _ in 0..3 {
+        transactions.push(db.transaction().await.unwrap());
+    }
+    for tx in &transactions {
+        let result = tx
+            .query_typed(
+                "SELECT j.journal_mode, s.synchronous, b.timeout, f.foreign_keys, c.cache_size
+                 FROM pragma_journal_mode() j, pragma_synchronous() s,
+                      pragma_busy_timeout() b, pragma_foreign_keys() f,
+                      pragma_cache_size() c",
+                vec![],
+            )
+            .await
+            .unwrap();
+        let row = &result.rows[0];
+        assert_eq!(row.get("journal_mode"), Some(&SqlValue::from("wal")));
+        assert_eq!(row.get("synchronous"), Some(&SqlValue::Int(1)));
+        assert_eq!(row.get("timeout"), Some(&SqlValue::Int(1234)));
+        assert_eq!(row.get("foreign_keys"), Some(&SqlValue::Int(1)));
+        assert_eq!(row.get("cache_size"), Some(&SqlValue::Int(-4000)));
+    }
+    for tx in transactions {
+        tx.rollback().await.unwrap();
+    }
+
+    db.close().await.unwrap();
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_memory_databases_are_isolated() {
+    let first = SqlBackend::sqlite(":memory:").await.unwrap();
+    let second = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    first.put("isolated", b"1".to_vec().into()).await.unwrap();
+    assert!(!second.exists("isolated").await.unwrap());
+
+    // Every pool connection sees the same database
+    let mut transactions = Vec::new();
+    for _ in 0..3 {
+        transactions.push(first.transaction().await.unwrap());
+    }
+    for tx in &transactions {
+        assert!(tx.exists("isolated").await.unwrap());
+    }
+    for tx in transactions {
+        tx.rollback().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_named_memory_database_is_shared() {
+    let name = format!(":memory:shared-{}", uuid::Uuid::new_v4().simple());
+    let first = SqlBackend::sqlite(&name).await.unwrap();
+    let second = SqlBackend::sqlite(&name).await.unwrap();
+
+    first.put("shared", b"1".to_vec().into()).await.unwrap();
+    assert!(second.exists("shared").await.unwrap());
+
+    assert!(matches!(
+        SqlBackend::sqlite(":memory:not/valid").await,
+        Err(SqlError::Connection(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_read_replica_routing() {
+    let primary_path = temp_db_path();
+    let replica_path = temp_db_path();
+
+    // Stand-in for a replica that has not caught up yet
+    let replica = SqlBackend::sqlite(&replica_path).await.unwrap();
+    replica.put("replicated", b"old".to_vec().into()).await.unwrap();
+    replica.close().await.unwrap();
+
+    let db = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(primary_path.clone()),
+        SqlBackendOptions::new()
+            .read_replica(format!("sqlite:{}", replica_path)),
+    )
+    .await
+    .unwrap();
+
+    db.put("written", b"new".to_vec().into()).await.unwrap();
+    assert_eq!(db.get("written").await.unwrap(), None);
+    assert!(!db.exists("written").await.unwrap());
+    assert!(db.exists("replicated").await.unwrap());
+    assert_eq!(db.scan("").await.unwrap().keys, vec!["replicated"]);
+
+    let primary = db.primary();
+    assert_eq!(
+        primary.get("written").await.unwrap().unwrap().as_bytes(),
+        b"new"
+    );
+    assert!(!primary.exists("replicated").await.unwrap());
+    assert_eq!(primary.scan("").await.unwrap().keys, vec!["written"]);
+
+    // SELECTs read the replica, everything else runs on the primary
+    let count = "SELECT COUNT(*) AS n FROM kv_store WHERE key = ?";
+    db.query(
+        "UPDATE kv_store SET value = ? WHERE key = ?",
+        vec![b"newer".to_vec().into(), b"written".to_vec().into()],
+    )
+    .await
+    .unwrap();
+    let on_replica =
+        db.query_typed(count, vec!["written".into()]).await.unwrap();
+    assert_eq!(on_replica.rows[0].get("n"), Some(&SqlValue::Int(0)));
+    let on_primary =
+        primary.query_typed(count, vec!["written".into()]).await.unwrap();
+    assert_eq!(on_primary.rows[0].get("n"), Some(&SqlValue::Int(1)));
+    let cte = "WITH w AS (SELECT key FROM kv_store WHERE key = ?) \
+               SELECT COUNT(*) AS n FROM w";
+    let on_replica =
+        db.query_typed(cte, vec!["written".into()]).await.unwrap();
+    assert_eq!(on_replica.rows[0].get("n"), Some(&SqlValue::Int(0)));
+
+    let tx = db.transaction().await.unwrap();
+    assert!(tx.exists("written").await.unwrap());
+    tx.rollback().await.unwrap();
+
+    db.close().await.unwrap();
+    remove_db(&primary_path);
+    remove_db(&replica_path);
+}
+
+#[cfg(feature = "postgres")]
+#[tokio::test]
+async fn test_read_replica_must_match_primary() {
+    let result = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(":memory:".into()),
+        SqlBackendOptions::new().read_replica("postgres://localhost/agentsql"),
+    )
+    .await;
+    assert!(matches!(result, Err(SqlError::Connection(_))));
+}
+
+#[tokio::test]
+async fn test_health_and_pool_stats() {
+    use std::{sync::Arc, time::Duration};
+
+    let db = Arc::new(
+        SqlBackend::with_options(
+            SqlBackendConfig::Sqlite(":memory:".into()),
+            SqlBackendOptions::new().max_connections(1),
+        )
+        .await
+        .unwrap(),
+    );
+
+    let health = db.health(Duration::from_secs(5)).await.unwrap();
+    assert_eq!(health.backend_type, BackendType::Sqlite);
+    assert_eq!(health.schema_version, Some(6));
+    assert_eq!(health.pool.max_connections, 1);
+    assert_eq!(health.pool.size, 1);
+    assert_eq!(health.replica, None);
+
+    // Exhaust the pool and queue a reader behind it
+    let tx = db.transaction().await.unwrap();
+    assert_eq!(db.pool_stats().in_use, 1);
+    let reader = tokio::spawn({
+        let db = db.clone();
+        async move { db.get("queued").await }
+    });
+    while db.pool_stats().waiting == 0 {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    assert!(matches!(
+        db.ping(Duration::from_millis(50)).await,
+        Err(SqlError::Connection(_))
+    ));
+    let started = std::time::Instant::now();
+    assert!(matches!(
+        db.health(Duration::from_millis(50)).await,
+        Err(SqlError::Connection(_))
+    ));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    tx.rollback().await.unwrap();
+    assert_eq!(reader.await.unwrap().unwrap(), None);
+    assert_eq!(db.pool_stats().waiting, 0);
+    db.ping(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_read_only_mode() {
+    let path = temp_db_path();
+    let config = SqlBackendConfig::Sqlite(path.clone());
+    let read_only = || {
+        SqlBackend::with_options(
+            config.clone(),
+            SqlBackendOptions::new().read_only(true),
+        )
+    };
+
+    // Read-only never creates a database
+    assert!(matches!(read_only().await, Err(SqlError::Connection(_))));
+
+    let db = SqlBackend::sqlite(&path).await.unwrap();
+    db.put("existing", b"1".to_vec().into()).await.unwrap();
+    db.close().await.unwrap();
+
+    let db = read_only().await.unwrap();
+    assert!(db.exists("existing").await.unwrap());
+    assert_eq!(db.scan("").await.unwrap().keys, vec!["existing"]);
+    db.query_typed("SELECT key FROM kv_store", vec![]).await.unwrap();
+
+    // A CTE that reads is a SELECT too, one that writes is not
+    let cte =
+        "-- keys\nWITH k AS (SELECT key FROM kv_store) SELECT key FROM k";
+    let result = db.query_typed(cte, vec![]).await.unwrap();
+    assert_eq!(result.rows.len(), 1);
+    assert!(matches!(
+        db.query_typed(
+            "WITH k AS (SELECT key FROM kv_store) DELETE FROM kv_store",
+            vec![]
+        )
+        .await,
+        Err(SqlError::ReadOnly(_))
+    ));
+
+    assert!(matches!(
+        db.put("new", b"1".to_vec().into()).await,
+        Err(agentdb::AgentDbError::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        db.delete("existing").await,
+        Err(agentdb::AgentDbError::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        db.query_typed("DELETE FROM kv_store", vec![]).await,
+        Err(SqlError::ReadOnly(_))
+    ));
+    assert!(matches!(
+        db.execute_script("SELECT 1;").await,
+        Err(SqlError::ReadOnly(_))
+    ));
+    assert!(matches!(db.migrate().await, Err(SqlError::ReadOnly(_))));
+
+    let tx = db.transaction().await.unwrap();
+    assert!(tx.exists("existing").await.unwrap());
+    assert!(tx.put("new", b"1".to_vec().into()).await.is_err());
+    tx.rollback().await.unwrap();
+
+    db.close().await.unwrap();
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_table_prefixes_are_isolated() {
+    let path = temp_db_path();
+    let open = |prefix: &'static str| {
+        SqlBackend::with_options(
+            SqlBackendConfig::Sqlite(path.clone()),
+            SqlBackendOptions::new().table_prefix(prefix),
+        )
+    };
+    let alpha = open("alpha_").await.unwrap();
+    let beta = open("beta_").await.unwrap();
+
+    alpha.put("shared/key", b"alpha".to_vec().into()).await.unwrap();
+    beta.put("shared/key", b"beta".to_vec().into()).await.unwrap();
+    alpha.put("shared/only-alpha", b"1".to_vec().into()).await.unwrap();
+
+    assert_eq!(
+        alpha.get("shared/key").await.unwrap().unwrap().as_bytes(),
+        b"alpha"
+    );
+    assert_eq!(
+        beta.get("shared/key").await.unwrap().unwrap().as_bytes(),
+        b"beta"
+    );
+    assert_eq!(alpha.scan("shared/").await.unwrap().keys.len(), 2);
+    assert_eq!(beta.scan("shared/").await.unwrap().keys, vec!["shared/key"]);
+    assert!(!beta.exists("shared/only-alpha").await.unwrap());
+
+    beta.delete("shared/key").await.unwrap();
+    assert!(alpha.exists("shared/key").await.unwrap());
+
+    // Each prefix keeps its own ledger and tables
+    assert!(alpha.migration_status().await.unwrap().is_up_to_date());
+    assert!(beta.migration_status().await.unwrap().is_up_to_date());
+    assert_eq!(alpha.table_name("kv_store"), "alpha_kv_store");
+    let tables = alpha
+        .query_typed(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'beta\\_%' ESCAPE '\\'",
+            vec![],
+        )
+        .await
+        .unwrap();
+    let names: Vec<_> = tables
+        .rows
+        .iter()
+        .filter_map(|row| row.get_index(0).cloned())
+        .collect();
+    assert!(names.contains(&SqlValue::Text("beta_kv_store".into())));
+    assert!(names.contains(&SqlValue::Text("beta_schema_migrations".into())));
+    assert!(names.contains(&SqlValue::Text("beta_fs_inode".into())));
+
+    let sql = format!("SELECT key FROM {}", beta.table_name("kv_store"));
+    assert!(beta.query_typed(&sql, vec![]).await.unwrap().rows.is_empty());
+
+    alpha.close().await.unwrap();
+    beta.close().await.unwrap();
+    remove_db(&path);
+}
+
+#[tokio::test]
+async fn test_invalid_table_prefix_is_rejected() {
+    for prefix in [
+        "bad-prefix".to_string(),
+        "x; DROP TABLE kv_store".to_string(),
+        "a".repeat(33),
+    ] {
+        let result = SqlBackend::with_options(
+            SqlBackendConfig::Sqlite(":memory:".into()),
+            SqlBackendOptions::new().table_prefix(prefix.clone()),
+        )
+        .await;
+        assert!(matches!(result, Err(SqlError::Connection(_))), "{}", prefix);
+    }
+}
+
+#[tokio::test]
+async fn test_ttl() {
+    use std::time::Duration;
+
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+    assert!(db.capabilities().supports_ttl());
+
+    let short = Duration::from_millis(100);
+    db.put_with_ttl("scratch/a", b"1".to_vec().into(), short).await.unwrap();
+    db.put("scratch/b", b"2".to_vec().into()).await.unwrap();
+    db.expire("scratch/b", short).await.unwrap();
+    db.put_with_ttl(
+        "scratch/c",
+        b"3".to_vec().into(),
+        Duration::from_secs(3600),
+    )
+    .await
+    .unwrap();
+    db.put("scratch/d", b"4".to_vec().into()).await.unwrap();
+    assert_eq!(db.scan("scratch/").await.unwrap().keys.len(), 4);
+
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    assert!(db.get("scratch/a").await.unwrap().is_none());
+    assert!(!db.exists("scratch/b").await.unwrap());
+    assert_eq!(
+        db.scan("scratch/").await.unwrap().keys,
+        vec!["scratch/c", "scratch/d"]
+    );
+    assert!(matches!(
+        db.expire("scratch/a", short).await,
+        Err(SqlError::AgentDb(agentdb::AgentDbError::NotFound(_)))
+    ));
+
+    // A plain put revives the key without an expiry
+    db.put("scratch/a", b"5".to_vec().into()).await.unwrap();
+    assert!(db.exists("scratch/a").await.unwrap());
+
+    // Deleting an expired key reports it missing but still removes the row
+    db.put_with_ttl("scratch/e", b"7".to_vec().into(), Duration::ZERO)
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.delete("scratch/e").await,
+        Err(agentdb::AgentDbError::NotFound(_))
+    ));
+
+    assert_eq!(db.purge_expired().await.unwrap(), 1);
+    assert_eq!(db.purge_expired().await.unwrap(), 0);
+
+    let tx = db.transaction().await.unwrap();
+    tx.put_with_ttl("scratch/tx", b"6".to_vec().into(), Duration::ZERO)
+        .await
+        .unwrap();
+    assert!(!tx.exists("scratch/tx").await.unwrap());
+    assert!(matches!(
+        tx.delete("scratch/tx").await,
+        Err(agentdb::AgentDbError::NotFound(_))
+    ));
+    tx.commit().await.unwrap();
+    assert_eq!(db.purge_expired().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_ttl_after_plain_put_on_one_connection() {
+    use std::time::Duration;
+
+    // Statements are cached per connection, so both calls must share one
+    let options = SqlBackendOptions::new().max_connections(1);
+    for db in test_backends_with("ttl_one_connection", options).await {
+        let ttl = Duration::from_secs(60);
+        db.put("a", b"1".to_vec().into()).await.unwrap();
+        db.put_with_ttl("a", b"2".to_vec().into(), ttl).await.unwrap();
+        db.put("a", b"3".to_vec().into()).await.unwrap();
+        db.put_with_ttl("b", b"4".to_vec().into(), ttl).await.unwrap();
+
+        let (value, metadata) =
+            db.get_with_metadata("a").await.unwrap().unwrap();
+        assert_eq!(value.as_bytes(), b"3");
+        assert_eq!((metadata.version, metadata.expires_at), (3, None));
+        let (_, metadata) = db.get_with_metadata("b").await.unwrap().unwrap();
+        assert!(metadata.expires_at.is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_ttl_sweeper() {
+    use std::time::Duration;
+
+    let db = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(":memory:".into()),
+        SqlBackendOptions::new()
+            .ttl_sweeper(Duration::from_millis(50))
+            .ttl_sweep_batch_size(2),
+    )
+    .await
+    .unwrap();
+
+    for i in 0..5 {
+        db.put_with_ttl(
+            &format!("tmp/{}", i),
+            b"x".to_vec().into(),
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+    }
+    db.put("kept", b"x".to_vec().into()).await.unwrap();
+
+    let count = || async {
+        let result = db
+            .query_typed("SELECT COUNT(*) AS n FROM kv_store", vec![])
+            .await
+            .unwrap();
+        result.rows[0].get("n").cloned()
+    };
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(count().await, Some(SqlValue::Int(1)));
+
+    db.close().await.unwrap();
+
+    // A zero interval would panic inside the sweeper task
+    let result = SqlBackend::with_options(
+        SqlBackendConfig::Sqlite(":memory:".into()),
+        SqlBackendOptions::new().ttl_sweeper(Duration::ZERO),
+    )
+    .await;
+    assert!(matches!(result, Err(SqlError::Connection(_))));
+}
+
+#[tokio::test]
+async fn test_batch_lookups_match_get() {
+    for db in test_backends("batch_lookups").await {
+        db.put("foo", b"lower".to_vec().into()).await.unwrap();
+        db.put("bar ", b"padded".to_vec().into()).await.unwrap();
+
+        // Keys match exactly, by case and trailing spaces, in both paths
+        let keys = ["foo", "Foo", "FOO", "bar ", "bar"];
+        let mut single = Vec::new();
+        for key in keys {
+            single.push(db.get(key).await.unwrap());
+        }
+        let many = db.get_many(&keys).await.unwrap();
+        assert_eq!(many, single);
+        let found: Vec<bool> = many.iter().map(Option::is_some).collect();
+        assert_eq!(found, vec![true, false, false, true, false]);
+
+        assert_eq!(db.delete_many(&["Foo", "bar"]).await.unwrap(), 0);
+        assert!(db.exists("foo").await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_batch_operations() {
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    // More rows than fit in one statement on any dialect
+    let mut entries: Vec<(String, agentdb::Value)> = (0..2500)
+        .map(|i| (format!("mem/{:05}", i), i.to_string().into_bytes().into()))
+        .collect();
+    entries.push(("mem/00000".to_string(), b"last".to_vec().into()));
+    db.put_many(&entries).await.unwrap();
+    assert_eq!(db.scan("mem/").await.unwrap().keys.len(), 2500);
+
+    let keys: Vec<String> =
+        (0..2500).rev().map(|i| format!("mem/{:05}", i)).collect();
+    let values = db.get_many(&keys).await.unwrap();
+    assert_eq!(values.len(), 2500);
+    assert_eq!(values[0].as_ref().unwrap().as_bytes(), b"2499");
+    assert_eq!(values[2499].as_ref().unwrap().as_bytes(), b"last");
+
+    let values =
+        db.get_many(&["mem/00001", "missing", "mem/00001"]).await.unwrap();
+    assert_eq!(values[0].as_ref().unwrap().as_bytes(), b"1");
+    assert!(values[1].is_none());
+    assert_eq!(values[2], values[0]);
+
+    assert_eq!(db.delete_many(&keys[..2000]).await.unwrap(), 2000);
+    assert_eq!(db.delete_many(&["mem/02499", "missing"]).await.unwrap(), 0);
+    assert_eq!(db.scan("mem/").await.unwrap().keys.len(), 500);
+
+    // A failing row rolls back the whole batch, including earlier chunks
+    db.execute_script(
+        "CREATE TRIGGER reject_poison BEFORE INSERT ON kv_store
+         WHEN NEW.key = 'poison'
+         BEGIN SELECT RAISE(ABORT, 'poisoned'); END;",
+    )
+    .await
+    .unwrap();
+    let mut batch: Vec<(String, agentdb::Value)> = (0..1500)
+        .map(|i| (format!("batch/{}", i), b"x".to_vec().into()))
+        .collect();
+    batch.push(("poison".to_string(), b"x".to_vec().into()));
+    assert!(db.put_many(&batch).await.is_err());
+    assert!(db.scan("batch/").await.unwrap().keys.is_empty());
+
+    let tx = db.transaction().await.unwrap();
+    tx.put_many(&batch[..10]).await.unwrap();
+    assert!(tx.get_many(&["batch/3"]).await.unwrap()[0].is_some());
+    assert_eq!(tx.delete_many(&["batch/3", "batch/4"]).await.unwrap(), 2);
+    tx.rollback().await.unwrap();
+    assert!(db.scan("batch/").await.unwrap().keys.is_empty());
+}
+
+#[tokio::test]
+async fn test_compare_and_swap() {
+    use std::time::Duration;
+
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+
+    db.put("doc", b"a".to_vec().into()).await.unwrap();
+    db.put("doc", b"b".to_vec().into()).await.unwrap();
+    let current = db.get_versioned("doc").await.unwrap().unwrap();
+    assert_eq!(current.value.as_bytes(), b"b");
+    assert_eq!(current.version, 2);
+
+    assert_eq!(
+        db.put_if_version("doc", b"stale".to_vec().into(), 1).await.unwrap(),
+        CasOutcome::Conflict { current: Some(2) }
+    );
+    assert_eq!(
+        db.put_if_version("doc", b"c".to_vec().into(), 2).await.unwrap(),
+        CasOutcome::Applied { version: 3 }
+    );
+    assert_eq!(
+        db.put_if_version("missing", b"x".to_vec().into(), 1).await.unwrap(),
+        CasOutcome::Conflict { current: None }
+    );
+
+    assert_eq!(
+        db.put_if_absent("doc", b"x".to_vec().into()).await.unwrap(),
+        CasOutcome::Conflict { current: Some(3) }
+    );
+    assert!(
+        db.put_if_absent("fresh", b"x".to_vec().into())
+            .await
+            .unwrap()
+            .is_applied()
+    );
+    db.put_with_ttl("lease", b"old".to_vec().into(), Duration::ZERO)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.put_if_absent("lease", b"new".to_vec().into()).await.unwrap(),
+        CasOutcome::Applied { version: 1 }
+    );
+    assert_eq!(db.get("lease").await.unwrap().unwrap().as_bytes(), b"new");
+
+    assert!(!db.delete_if_version("doc", 2).await.unwrap().is_applied());
+    assert_eq!(
+        db.delete_if_version("doc", 3).await.unwrap(),
+        CasOutcome::Applied { version: 3 }
+    );
+    assert!(db.get_versioned("doc").await.unwrap().is_none());
+
+    db.put_many(&[("fresh".to_string(), b"y".to_vec().into())]).await.unwrap();
+    assert_eq!(db.get_versioned("fresh").await.unwrap().unwrap().version, 2);
+}
+
+#[tokio::test]
+async fn test_compare_and_swap_is_race_free() {
+    use std::sync::Arc;
+
+    let db = Arc::new(SqlBackend::sqlite(":memory:").await.unwrap());
+    db.put("counter", b"0".to_vec().into()).await.unwrap();
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..8 {
+        let db = db.clone();
+        tasks.spawn(async move {
+            for _ in 0..5 {
+                loop {
+                    let current =
+                        db.get_versioned("counter").await.unwrap().unwrap();
+                    let n: i64 = std::str::from_utf8(current.value.as_bytes())
+                        .unwrap()
+                        .parse()
+                        .unwrap();
+                    let next = (n + 1).to_string().into_bytes().into();
+                    let outcome = db
+                        .put_if_version("counter", next, current.version)
+                        .await
+                        .unwrap();
+                    if outcome.is_applied() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.unwrap();
+    }
+
+    let counter = db.get_versioned("counter").await.unwrap().unwrap();
+    assert_eq!(counter.value.as_bytes(), b"40");
+    assert_eq!(counter.version, 41);
+}
+
+#[tokio::test]
+async fn test_counters() {
+    use std::{sync::Arc, time::Duration};
+
+    let db = Arc::new(SqlBackend::sqlite(":memory:").await.unwrap());
+
+    assert_eq!(db.increment("steps", 1).await.unwrap(), 1);
+    assert_eq!(db.increment("steps", 41).await.unwrap(), 42);
+    assert_eq!(db.decrement("steps", 50).await.unwrap(), -8);
+    assert_eq!(db.get("steps").await.unwrap().unwrap().as_bytes(), b"-8");
+    assert_eq!(db.get_versioned("steps").await.unwrap().unwrap().version, 3);
+
+    // Counters written with put work too
+    db.put("tokens", b"100".to_vec().into()).await.unwrap();
+    assert_eq!(db.increment("tokens", 5).await.unwrap(), 105);
+
+    for bad in [&b"abc"[..], b"1.5", b"", b"12 ", b"\xff"] {
+        db.put("bad", bad.to_vec().into()).await.unwrap();
+        assert!(
+            matches!(
+                db.increment("bad", 1).await,
+                Err(SqlError::NotAnInteger(ref key)) if key == "bad"
+            ),
+            "{:?}",
+            bad
+        );
+        assert_eq!(db.get("bad").await.unwrap().unwrap().as_bytes(), bad);
+    }
+
+    db.put("max", i64::MAX.to_string().into_bytes().into()).await.unwrap();
+    assert!(db.increment("max", 1).await.is_err());
+    assert_eq!(
+        db.get("max").await.unwrap().unwrap().as_bytes(),
+        i64::MAX.to_string().as_bytes()
+    );
+    assert!(db.decrement("max", i64::MIN).await.is_err());
+
+    // An expired counter starts over
+    db.put_with_ttl("lease", b"7".to_vec().into(), Duration::ZERO)
+        .await
+        .unwrap();
+    assert_eq!(db.increment("lease", 1).await.unwrap(), 1);
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..8 {
+        let db = db.clone();
+        tasks.spawn(async move {
+            for _ in 0..25 {
+                db.increment("shared", 2).await.unwrap();
+            }
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.unwrap();
+    }
+    assert_eq!(db.increment("shared", 0).await.unwrap(), 400);
+}
+
+#[tokio::test]
+async fn test_counter_rules_match_across_backends() {
+    for db in test_backends("counter_rules").await {
+        let backend = db.backend_type();
+        for bad in [
+            "007",
+            "-0",
+            "+1",
+            "1x",
+            " 1",
+            "99999999999999999999",
+            "9223372036854775808",
+            "-9223372036854775809",
+        ] {
+            db.put("bad", bad.as_bytes().to_vec().into()).await.unwrap();
+            assert!(
+                matches!(
+                    db.increment("bad", 1).await,
+                    Err(SqlError::NotAnInteger(ref key)) if key == "bad"
+                ),
+                "{:?} {:?}",
+                backend,
+                bad
+            );
+            assert_eq!(
+                db.get("bad").await.unwrap().unwrap().as_bytes(),
+                bad.as_bytes()
+            );
+        }
+
+        for (start, delta, sum) in [
+            ("0", 0, 0),
+            ("-5", 5, 0),
+            ("-9223372036854775808", 1, i64::MIN + 1),
+        ] {
+            db.put("good", start.as_bytes().to_vec().into()).await.unwrap();
+            assert_eq!(db.increment("good", delta).await.unwrap(), sum);
+        }
+
+        // Sums outside i64 fail the same way and leave the value alone
+        for (start, delta) in [(i64::MAX, 1), (i64::MIN, -1)] {
+            let start = start.to_string();
+            db.put("edge", start.as_bytes().to_vec().into()).await.unwrap();
+            let err = db.increment("edge", delta).await.unwrap_err();
+            assert!(
+                matches!(err, SqlError::Query(ref msg) if msg.contains("overflows")),
+                "{:?} {:?}",
+                backend,
+                err
+            );
+            assert_eq!(
+                db.get("edge").await.unwrap().unwrap().as_bytes(),
+                start.as_bytes()
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_scan_pages() {
+    use std::time::Duration;
+
+    let db = SqlBackend::sqlite(":memory:").await.unwrap();
+    let keys = ["m/a", "m/B", "m/c", "m/é", "m/b", "m/A", "n/a", "l/z"];
+    for key in keys {
+        db.put(key, b"x".to_vec().into()).await.unwrap();
+    }
+    db.put_with_ttl("m/gone", b"x".to_vec().into(), Duration::ZERO)
+        .await
+        .unwrap();
+
+    // Byte-wise order: upper case before lower case, multi-byte last
+    let all = db.scan_page(&ScanOptions::new().prefix("m/")).await.unwrap();
+    assert_eq!(all.keys, vec!["m/A", "m/B", "m/a", "m/b", "m/c", "m/é"]);
+    assert_eq!(all.next, None);
+    assert_eq!(db.scan("m/").await.unwrap().keys, all.keys);
+
+    for order in [ScanOrder::Ascending, ScanOrder::Descending] {
+        let options = ScanOptions::new().prefix("m/").limit(4).order(order);
+        let mut seen = Vec::new();
+        let mut page = db.scan_page(&options).await.unwrap();
+        let mut pages = 1;
+        loop {
+            assert!(page.keys.len() <= 4);
+            seen.extend(page.keys);
+            let Some(cursor) = page.next else { break };
+            // Cursors survive a trip through a string
+            let cursor: ScanCursor = cursor.to_string().parse().unwrap();
+            page = db.scan_page(&options.clone().after(cursor)).await.unwrap();
+            pages += 1;
+        }
+        let mut expected = all.keys.clone();
+        if order == ScanOrder::Descending {
+            expected.reverse();
+        }
+        assert_eq!(seen, expected);
+        assert_eq!(pages, 2);
+    }
+
+    // An exact fit leaves no cursor
+    let page =
+        db.scan_page(&ScanOptions::new().prefix("m/").limit(6)).await.unwrap();
+    assert_eq!((page.keys.len(), page.next), (6, None));
+
+    let bounded = db
+        .scan_page(&ScanOptions::new().start("m/B").end("m/c"))
+        .await
+        .unwrap();
+    assert_eq!(bounded.keys, vec!["m/B", "m/a", "m/b"]);
+    let bounded = db
+        .scan_page(
+            &ScanOptions::new()
+                .start("m")
+                .end("n/b")
+                .order(ScanOrder::Descending)
+                .limit(1),
+        )
+        .await
+        .unwrap();
+    assert_eq!(bounded.keys, vec!["n/a"]);
+
+    assert!("not hex".parse::<ScanCursor>().is_err());
+    assert!("ff".parse::<ScanCursor>().is_err());
+}
+
+#[tokio::test]
+async fn test_scan_prefix_is_exact() {
+    let keys = [
+        "a%b",
+        "a%c",
+        "axb",
+        "a_b",
+        "aXb",
+        "A%b",
+        "a\\b",
+        "a\\",
+        "ab",
+        "ab ",
+        "abc",
+        "ab\u{10FFFF}",
+        "ac",
+        "é",
+        "éa",
+        "f",
+    ];
+
+    for db in test_backends("scan_prefix").await {
+        let backend = db.backend_type();
+        for key in keys {
+            db.put(key, b"x".to_vec().into()).await.unwrap();
+        }
+
+        let cases: [(&str, &[&str]); 8] = [
+            ("a%", &["a%b", "a%c"]),
+            ("a_", &["a_b"]),
+            ("A", &["A%b"]),
+            ("a\\", &["a\\", "a\\b"]),
+            ("ab", &["ab", "ab ", "abc", "ab\u{10FFFF}"]),
+            ("ab ", &["ab "]),
+            ("é", &["é", "éa"]),
+            ("\u{10FFFF}", &[]),
+        ];
+        for (prefix, expected) in cases {
+            assert_eq!(
+                db.scan(prefix).await.unwrap().keys,
+                expected.to_vec(),
+                "{:?} prefix {:?}",
+                backend,
+                prefix
+            );
+        }
+
+        let page = db
+            .scan_page(
+                &ScanOptions::new()
+                    .prefix("a")
+                    .order(ScanOrder::Descending)
+                    .limit(3),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            page.keys,
+            vec!["axb", "ac", "ab\u{10FFFF}"],
+            "{:?}",
+            backend
+        );
+        assert_eq!(db.scan("").await.unwrap().keys.len(), keys.len());
+    }
+}
+
+#[tokio::test]
+async fn test_scan_entries() {
+    use futures_util::StreamExt;
+    use std::time::Duration;
+
+    for db in test_backends("scan_entries").await {
+        for i in 0..300 {
+            let value = format!("value-{}", i);
+            db.put(&format!("e/{:04}", i), value.into_bytes().into())
+                .await
+                .unwrap();
+        }
+        db.put("e/0007", b"again".to_vec().into()).await.unwrap();
+        db.put_with_ttl(
+            "e/0008",
+            b"x".to_vec().into(),
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+        db.put_with_ttl("e/0009", b"x".to_vec().into(), Duration::ZERO)
+            .await
+            .unwrap();
+        db.put("f/0000", b"other".to_vec().into()).await.unwrap();
+
+        let entries: Vec<Entry> = db
+            .scan_entries("e/")
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect()
+            .await;
+
+        // Expired entries and other prefixes are skipped, order is by key
+        assert_eq!(entries.len(), 299);
+        let keys: Vec<&str> =
+            entries.iter().map(|(k, _, _)| k.as_str()).collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+        assert!(!keys.contains(&"e/0009"));
+
+        let (_, value, metadata) = &entries[0];
+        assert_eq!(value.as_bytes(), b"value-0");
+        assert_eq!(
+            (metadata.version, metadata.size, metadata.expires_at),
+            (1, 7, None)
+        );
+        let (key, value, metadata) = &entries[7];
+        assert_eq!(
+            (key.as_str(), value.as_bytes()),
+            ("e/0007", &b"again"[..])
+        );
+        assert_eq!((metadata.version, metadata.size), (2, 5));
+        let expires_at = entries[8].2.expires_at.unwrap();
+        assert!(expires_at > chrono::Utc::now());
+
+        // Dropping a stream early hands its connection back to the pool
+        for _ in 0..10 {
+            let mut stream = db.scan_entries("e/").await.unwrap();
+            assert!(stream.next().await.unwrap().is_ok());
+        }
+        tokio::time::timeout(Duration::from_secs(5), db.get("e/0000"))
+            .await
+            .expect("connection was not released")
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_list_prefixes() {
+    use std::time::Duration;
+
+    for db in test_backends("list").await {
+        let keys = [
+            "agent/1/name",
+            "agent/1/memory/episodic/a",
+            "agent/1/memory/episodic/b",
+            "agent/1/memory/semantic/a",
+            "agent/1/notes/",
+            "agent/1/tools/x",
+            "agent/10/name",
+            "agent/2/name",
+            "other/1",
+        ];
+        for key in keys {
+            db.put(key, b"x".to_vec().into()).await.unwrap();
+        }
+        db.put_with_ttl(
+            "agent/1/gone/x",
+            b"x".to_vec().into(),
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+
+        let page = db.list("agent/1/", "/").await.unwrap();
+        assert_eq!(page.keys, vec!["agent/1/name"]);
+        assert_eq!(
+            page.common_prefixes,
+            vec!["agent/1/memory/", "agent/1/notes/", "agent/1/tools/"]
+        );
+        assert_eq!(page.next, None);
+
+        let page = db.list("agent/", "/").await.unwrap();
+        assert!(page.keys.is_empty());
+        assert_eq!(
+            page.common_prefixes,
+            vec!["agent/1/", "agent/10/", "agent/2/"]
+        );
+
+        // A multi-character delimiter, and none at all
+        let page = db.list("agent/1/memory", "/e").await.unwrap();
+        assert_eq!(page.common_prefixes, vec!["agent/1/memory/e"]);
+        assert_eq!(page.keys, vec!["agent/1/memory/semantic/a"]);
+        let page = db.list("agent/1/memory/", "").await.unwrap();
+        assert_eq!(page.keys.len(), 3);
+        assert!(page.common_prefixes.is_empty());
+
+        // Paging in either order visits each entry once, whether a page
+        // ends on a key or on a common prefix
+        let all = ["agent/1/", "agent/10/", "agent/2/"];
+        for limit in 1..=3 {
+            for order in [ScanOrder::Ascending, ScanOrder::Descending] {
+                let options = ScanOptions::new()
+                    .prefix("agent/")
+                    .limit(limit)
+                    .order(order);
+                let mut seen = Vec::new();
+                let mut page = db.list_page(&options, "/").await.unwrap();
+                loop {
+                    assert!(page.keys.is_empty());
+                    assert!(page.common_prefixes.len() <= limit);
+                    seen.extend(page.common_prefixes);
+                    let Some(cursor) = page.next else { break };
+                    let options = options.clone().after(cursor);
+                    page = db.list_page(&options, "/").await.unwrap();
+                }
+                let mut expected = all.to_vec();
+                if order == ScanOrder::Descending {
+                    expected.reverse();
+                }
+                assert_eq!(seen, expected, "limit {} {:?}", limit, order);
+            }
+        }
+
+        // Keys and common prefixes share a page
+        let options = ScanOptions::new().prefix("agent/1/").limit(2);
+        let page = db.list_page(&options, "/").await.unwrap();
+        assert_eq!(page.common_prefixes, vec!["agent/1/memory/"]);
+        assert_eq!(page.keys, vec!["agent/1/name"]);
+        let options = options.after(page.next.unwrap());
+        let page = db.list_page(&options, "/").await.unwrap();
+        assert_eq!(
+            page.common_prefixes,
+            vec!["agent/1/notes/", "agent/1/tools/"]
+        );
+        assert!(page.keys.is_empty());
+        assert_eq!(page.next, None);
+    }
+}
+
+#[tokio::test]
+async fn test_list_beyond_one_fetch() {
+    for db in test_backends("list_large").await {
+        // More keys than one internal fetch, both under a single common
+        // prefix and directly under the listed prefix
+        let entries: Vec<(String, agentdb::Value)> = (0..1500)
+            .flat_map(|i| {
+                [
+                    (format!("big/sub/{:04}", i), b"x".to_vec().into()),
+                    (format!("big/{:04}", i), b"x".to_vec().into()),
+                ]
+            })
+            .collect();
+        db.put_many(&entries).await.unwrap();
+        db.put("big/zz/a", b"x".to_vec().into()).await.unwrap();
+
+        let page = db.list("big/", "/").await.unwrap();
+        assert_eq!(page.common_prefixes, vec!["big/sub/", "big/zz/"]);
+        assert_eq!(page.keys.len(), 1500);
+        assert_eq!(page.keys.first().map(String::as_str), Some("big/0000"));
+        assert_eq!(page.keys.last().map(String::as_str), Some("big/1499"));
+        assert_eq!(page.next, None);
+
+        let options =
+            ScanOptions::new().prefix("big/").order(ScanOrder::Descending);
+        let page = db.list_page(&options, "/").await.unwrap();
+        assert_eq!(page.common_prefixes, vec!["big/zz/", "big/sub/"]);
+        assert_eq!(page.keys.len(), 1500);
+        assert_eq!(page.keys.first().map(String::as_str), Some("big/1499"));
+    }
+}
+
+#[tokio::test]
+async fn test_entry_metadata() {
+    use std::time::Duration;
+
+    let pause = || tokio::time::sleep(Duration::from_millis(5));
+    for db in test_backends("entry_metadata").await {
+        assert_eq!(db.get_with_metadata("k").await.unwrap(), None);
+
+        let before = chrono::Utc::now();
+        db.put("k", b"hello".to_vec().into()).await.unwrap();
+        let (value, created) =
+            db.get_with_metadata("k").await.unwrap().unwrap();
+        assert_eq!(value.as_bytes(), b"hello");
+        assert_eq!((created.version, created.size), (1, 5));
+        assert_eq!(created.created_at, created.updated_at);
+        assert!(created.created_at >= before - Duration::from_millis(1));
+        assert!(created.created_at <= chrono::Utc::now());
+
+        // Every kind of write moves updated_at but keeps created_at
+        pause().await;
+        db.put_with_ttl("k", b"hi".to_vec().into(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        pause().await;
+        db.put_if_version("k", b"7".to_vec().into(), 2).await.unwrap();
+        pause().await;
+        db.increment("k", 1).await.unwrap();
+        let (_, updated) = db.get_with_metadata("k").await.unwrap().unwrap();
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at > created.updated_at);
+        assert_eq!((updated.version, updated.size), (4, 1));
+
+        let tx = db.transaction().await.unwrap();
+        let (_, in_tx) = tx.get_with_metadata("k").await.unwrap().unwrap();
+        assert_eq!(in_tx, updated);
+        tx.rollback().await.unwrap();
+
+        // modified_since keeps writes at or after the given time
+        db.put_many(&[
+            ("a".to_string(), b"1".to_vec().into()),
+            ("b".to_string(), b"2".to_vec().into()),
+        ])
+        .await
+        .unwrap();
+        pause().await;
+        let since = chrono::Utc::now();
+        db.put_if_absent("c", b"3".to_vec().into()).await.unwrap();
+        db.put("a", b"4".to_vec().into()).await.unwrap();
+        db.put_with_ttl("d", b"5".to_vec().into(), Duration::ZERO)
+            .await
+            .unwrap();
+
+        let page = db
+            .scan_page(&ScanOptions::new().modified_since(since))
+            .await
+            .unwrap();
+        assert_eq!(page.keys, vec!["a", "c"]);
+        let page = db
+            .scan_page(&ScanOptions::new().modified_since(before))
+            .await
+            .unwrap();
+        assert_eq!(page.keys, vec!["a", "b", "c", "k"]);
+    }
+}
+
+#[tokio::test]
+async fn test_timestamp_migration() {
+    let path = temp_db_path();
+    let config = SqlBackendConfig::Sqlite(path.clone());
+    let options = SqlBackendOptions::new().migration_mode(MigrationMode::Skip);
+
+    // Rows as written before timestamps were normalized
+    let db = SqlBackend::with_options(config.clone(), options).await.unwrap();
+    db.migrate_to(4).await.unwrap();
+    db.query(
+        "INSERT INTO kv_store (key, value, created_at, updated_at) VALUES \
+         ('text', X'31', 1700000000, '2024-01-02 03:04:05'), \
+         ('seconds', X'32', 1700000000, 1700000001), \
+         ('missing', X'33', 1700000000, NULL)",
+        vec![],
+    )
+    .await
+    .unwrap();
+    db.migrate().await.unwrap();
+
+    let timestamps = |key: &str| {
+        let db = &db;
+        let key = key.to_string();
+        async move {
+            let (_, metadata) =
+                db.get_with_metadata(&key).await.unwrap().unwrap();
+            (
+                metadata.created_at.timestamp_millis(),
+                metadata.updated_at.timestamp_millis(),
+            )
+        }
+    };
+    assert_eq!(timestamps("text").await, (1700000000000, 1704164645000));
+    assert_eq!(timestamps("seconds").await, (1700000000000, 1700000001000));
+    assert_eq!(timestamps("missing").await, (1700000000000, 1700000000000));
+
+    // Raw inserts get millisecond defaults
+    let before = chrono::Utc::now().timestamp_millis();
+    db.query(
+        "INSERT INTO kv_store (key, value) VALUES ('raw', X'34')",
+        vec![],
+    )
+    .await
+    .unwrap();
+    let (raw_created, raw_updated) = timestamps("raw").await;
+    assert!(raw_created >= before - 1 && raw_created == raw_updated);
+
+    db.close().await.unwrap();
+    remove_db(&path);
+}